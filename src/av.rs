@@ -22,18 +22,15 @@
 //! Safe interface to `toxav`.
 
 
-//use std::cell::RefCell;
-
-
-use libc::{/*c_int,*/ c_uint, c_void};
+use libc::{c_uint, c_void};
 use std::thread::sleep;
 use std::cmp::max;
 use std::slice;
 use std::time::Duration;
-use std::mem;
-use std::sync::mpsc::Sender;
+use std::mem::MaybeUninit;
 
-use crate::core::{Tox, Event};
+use crate::core::{Tox, EventSender, EventRef, FriendNumber, catch_panic};
+use crate::core::errors::CallbackPanic;
 
 pub mod ll;
 pub mod errors;
@@ -47,21 +44,21 @@ pub fn av_version_major() -> u32 {
 }
 
 #[test]
-// Current major version should equal to `0`
+// `toxav` is a part of `toxcore` and shares its version
 fn test_av_version_major() {
-    assert_eq!(av_version_major(), 0);
+    assert_eq!(av_version_major(), crate::core::version().major);
 }
 
 
 /// Return the minor version of the `toxav` library.
 pub fn av_version_minor() -> u32 {
-    unsafe { ll::toxav_version_major() }
+    unsafe { ll::toxav_version_minor() }
 }
 
 #[test]
-// Current minor version should equal to `0`
+// `toxav` is a part of `toxcore` and shares its version
 fn test_av_version_minor() {
-    assert_eq!(av_version_minor(), 0);
+    assert_eq!(av_version_minor(), crate::core::version().minor);
 }
 
 
@@ -71,9 +68,9 @@ pub fn av_version_patch() -> u32 {
 }
 
 #[test]
-// Current patch version should equal to `0`
+// `toxav` is a part of `toxcore` and shares its version
 fn test_av_version_patch() {
-    assert_eq!(av_version_patch(), 0);
+    assert_eq!(av_version_patch(), crate::core::version().patch);
 }
 
 
@@ -100,11 +97,11 @@ fn test_av_version_is_compatible() {
 
 macro_rules! tox_try {
     ($err:ident, $exp:expr) => {{
-        let mut $err = ::std::mem::uninitialized();
+        let mut $err = MaybeUninit::uninit();
         let res = $exp;
-        match $err as c_uint {
+        match $err.assume_init() as c_uint {
             0 => {},
-            _ => return Err($err),
+            _ => return Err($err.assume_init()),
         };
         res
     }};
@@ -182,25 +179,50 @@ pub enum FriendCallState {
 // Creation and destruction //
 /////////////////////////////
 
-#[derive(Clone, PartialEq, Eq)]
+/**
+    A/V session bound to a `Tox` instance.
+
+    AV events are delivered through the event queue of the `Tox` instance the
+    session was created for, so they show up in `Tox::iter()`, unless they are
+    passed to the handler of `tick_with`. The `Tox`
+    instance itself is only killed after both `Tox` and `ToxAv` are dropped.
+*/
 pub struct ToxAv {
-    av: *mut ll::ToxAV
+    av: *mut ll::ToxAV,
+    callbacks: Box<Callbacks>,
 }
 
-unsafe impl Send for ToxAv {}
+/// What the callbacks get as user data
+struct Callbacks {
+    // Also keeps the toxcore instance alive
+    events: EventSender,
+    /// Points to the `&mut dyn FnMut(EventRef)` given to `tick_with` during
+    /// its iteration, the events are queued otherwise
+    handler: Option<*mut c_void>,
+    /// The first panic of the iteration
+    panic: Option<CallbackPanic>,
+}
 
 impl ToxAv {
     pub fn new(tox: &mut Tox) -> Result<ToxAv, errors::NewAvError> {
+        let av = unsafe {
+            tox_try!(err, ll::toxav_new(tox.raw, err.as_mut_ptr()))
+        };
         let mut toxav = ToxAv {
-            av: unsafe { tox_try!(err, ll::toxav_new(tox.raw, &mut err)) }
+            av,
+            callbacks: Box::new(Callbacks {
+                events: tox.event_tx.clone(),
+                handler: None,
+                panic: None,
+            }),
         };
-        toxav.init(tox);
+        toxav.init();
         Ok(toxav)
     }
 
-    fn init(&mut self, tox: &mut Tox) {
+    fn init(&mut self) {
         unsafe {
-            let chan: *mut c_void = mem::transmute(&mut *tox.event_tx);
+            let chan = &mut *self.callbacks as *mut Callbacks as *mut c_void;
             ll::toxav_callback_call(self.av, on_call, chan);
             ll::toxav_callback_call_state(self.av, on_call_state, chan);
             ll::toxav_callback_bit_rate_status(self.av, on_bit_rate_status, chan);
@@ -216,79 +238,107 @@ impl ToxAv {
         }
     }
 
-    pub fn tick(&mut self) {
+    /// Does nothing while the event queue of the `Tox` is over its limit, see
+    /// `OverflowPolicy`
+    ///
+    /// Panics in the callbacks are caught instead of unwinding into toxav.
+    /// The first one is returned once the iteration is over. Panics if
+    /// called from a callback of this `ToxAv` or of its `Tox`.
+    pub fn tick(&mut self) -> Result<(), CallbackPanic> {
+        if self.callbacks.events.blocks() {
+            return Ok(());
+        }
+        self.iterate(None)
+    }
+
+    /**
+        Like `tick()`, but passes the events to `handler` as they happen
+        instead of queueing them, so audio and video frames aren't copied.
+    */
+    pub fn tick_with(&mut self, mut handler: &mut dyn FnMut(EventRef)) -> Result<(), CallbackPanic> {
+        let handler = &mut handler as *mut &mut dyn FnMut(EventRef) as *mut c_void;
+        self.iterate(Some(handler))
+    }
+
+    fn iterate(&mut self, handler: Option<*mut c_void>) -> Result<(), CallbackPanic> {
+        let instance = self.callbacks.events.instance.clone();
+        instance.begin_iteration();
+        self.callbacks.handler = handler;
         unsafe { ll::toxav_iterate(self.av) };
+        self.callbacks.handler = None;
+        instance.end_iteration();
+        self.callbacks.panic.take().map_or(Ok(()), Err)
     }
 
     pub fn call(
         &mut self,
-        friend_number: u32,
+        friend_number: FriendNumber,
         audio_bitrate: u32,
         video_bitrate: u32
     ) -> Result<bool, errors::CallError> {
         Ok(unsafe {
             tox_try!(err, ll::toxav_call(
                 self.av,
-                friend_number,
+                friend_number.0,
                 audio_bitrate,
                 video_bitrate,
-                &mut err
+                err.as_mut_ptr()
             ))
         })
     }
 
     pub fn answer(
         &mut self,
-        friend_number: u32,
+        friend_number: FriendNumber,
         audio_bitrate: u32,
         video_bitrate: u32
     ) -> Result<bool, errors::AnswerError> {
         Ok(unsafe {
             tox_try!(err, ll::toxav_answer(
                 self.av,
-                friend_number,
+                friend_number.0,
                 audio_bitrate,
                 video_bitrate,
-                &mut err
+                err.as_mut_ptr()
             ))
         })
     }
 
     pub fn control(
         &mut self,
-        friend_number: u32,
+        friend_number: FriendNumber,
         control: CallControl
     ) -> Result<bool, errors::CallControlError> {
         Ok(unsafe {
             tox_try!(err, ll::toxav_call_control(
                 self.av,
-                friend_number,
+                friend_number.0,
                 control,
-                &mut err
+                err.as_mut_ptr()
             ))
         })
     }
 
     pub fn set_bitrate(
         &mut self,
-        friend_number: u32,
+        friend_number: FriendNumber,
         audio_bitrate: i32,
         video_bitrate: i32,
     ) -> Result<bool, errors::BitRateSetError> {
         Ok(unsafe {
             tox_try!(err, ll::toxav_bit_rate_set(
                 self.av,
-                friend_number,
+                friend_number.0,
                 audio_bitrate,
                 video_bitrate,
-                &mut err
+                err.as_mut_ptr()
             ))
         })
     }
 
     pub fn send_audio(
         &mut self,
-        friend_number: u32,
+        friend_number: FriendNumber,
         pcm: &[i16],
         sample_count: usize,
         channels: u8,
@@ -297,19 +347,19 @@ impl ToxAv {
         Ok(unsafe {
             tox_try!(err, ll::toxav_audio_send_frame(
                 self.av,
-                friend_number,
+                friend_number.0,
                 pcm.as_ptr(),
                 sample_count,
                 channels,
                 sampling_rate,
-                &mut err
+                err.as_mut_ptr()
             ))
         })
     }
 
     pub fn send_video(
         &mut self,
-        friend_number: u32,
+        friend_number: FriendNumber,
         width: u16,
         height: u16,
         y: &[u8], u: &[u8], v: &[u8]
@@ -317,11 +367,11 @@ impl ToxAv {
         Ok(unsafe {
             tox_try!(err, ll::toxav_video_send_frame(
                 self.av,
-                friend_number,
+                friend_number.0,
                 width,
                 height,
                 y.as_ptr(), u.as_ptr(), v.as_ptr(),
-                &mut err
+                err.as_mut_ptr()
             ))
         })
     }
@@ -333,46 +383,64 @@ impl Drop for ToxAv {
     }
 }
 
-extern fn on_call(
-    toxav: *mut ll::ToxAV,
+/// Pass the event made by `f` to the handler or the queue of the `Callbacks` in `chan`
+unsafe fn guard<'a, F>(chan: *mut c_void, callback: &'static str, f: F)
+    where F: FnOnce() -> EventRef<'a>
+{
+    let callbacks = &mut *(chan as *mut Callbacks);
+    let result = catch_panic(callback, || {
+        let event = f();
+        match callbacks.handler {
+            Some(handler) => (*(handler as *mut &mut dyn FnMut(EventRef)))(event),
+            None => callbacks.events.send(event.to_event()),
+        }
+    });
+    if let Err(panic) = result {
+        callbacks.panic.get_or_insert(panic);
+    }
+}
+
+extern "C" fn on_call(
+    _: *mut ll::ToxAV,
     friend_number: u32,
     audio_enabled: bool,
     video_enabled: bool,
     chan: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = mem::transmute(chan);
-        tx.send(Event::Call(friend_number, audio_enabled, video_enabled)).ok();
+        guard(chan, "call", || {
+            EventRef::Call(FriendNumber(friend_number), audio_enabled, video_enabled)
+        });
     }
 }
 
-extern fn on_call_state(
-    toxav: *mut ll::ToxAV,
+extern "C" fn on_call_state(
+    _: *mut ll::ToxAV,
     friend_number: u32,
     state: u32,
     chan: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = mem::transmute(chan);
-        tx.send(Event::CallState(friend_number, state)).ok();
+        guard(chan, "call_state", || EventRef::CallState(FriendNumber(friend_number), state));
     }
 }
 
-extern fn on_bit_rate_status(
-    toxav: *mut ll::ToxAV,
+extern "C" fn on_bit_rate_status(
+    _: *mut ll::ToxAV,
     friend_number: u32,
     audio_bitrate: u32,
     video_bitrate: u32,
     chan: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = mem::transmute(chan);
-        tx.send(Event::BitRateStatus(friend_number, audio_bitrate, video_bitrate)).ok();
+        guard(chan, "bit_rate_status", || {
+            EventRef::BitRateStatus(FriendNumber(friend_number), audio_bitrate, video_bitrate)
+        });
     }
 }
 
-extern fn on_audio_receive_frame(
-    toxav: *mut ll::ToxAV,
+extern "C" fn on_audio_receive_frame(
+    _: *mut ll::ToxAV,
     friend_number: u32,
     pcm: *const i16,
     sample_count: usize,
@@ -381,14 +449,15 @@ extern fn on_audio_receive_frame(
     chan: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = mem::transmute(chan);
-        let pcm = slice::from_raw_parts(pcm, sample_count * channels as usize * 2);
-        tx.send(Event::AudioReceiveFrame(friend_number, pcm.to_vec(), sample_count, channels, sampling_rate)).ok();
+        guard(chan, "audio_receive_frame", || {
+            let pcm = slice::from_raw_parts(pcm, sample_count * channels as usize);
+            EventRef::AudioReceiveFrame(FriendNumber(friend_number), pcm, sample_count, channels, sampling_rate)
+        });
     }
 }
 
-extern fn on_video_receive_frame(
-    toxav: *mut ll::ToxAV,
+extern "C" fn on_video_receive_frame(
+    _: *mut ll::ToxAV,
     friend_number: u32,
     width: u16,
     height: u16,
@@ -401,10 +470,64 @@ extern fn on_video_receive_frame(
     chan: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = mem::transmute(chan);
-        let y = slice::from_raw_parts(y, max(width as i32, ystride.abs()) as usize * height as usize);
-        let u = slice::from_raw_parts(u, max((width / 2) as i32, ustride.abs()) as usize * (height / 2) as usize);
-        let v = slice::from_raw_parts(v, max((width / 2) as i32, vstride.abs()) as usize * (height / 2) as usize);
-        tx.send(Event::VideoReceiveFrame(friend_number, width, height, y.to_vec(), u.to_vec(), v.to_vec(), ystride, ustride, vstride)).ok();
+        guard(chan, "video_receive_frame", || {
+            let y = slice::from_raw_parts(y, max(width as i32, ystride.abs()) as usize * height as usize);
+            let u = slice::from_raw_parts(u, max((width / 2) as i32, ustride.abs()) as usize * (height / 2) as usize);
+            let v = slice::from_raw_parts(v, max((width / 2) as i32, vstride.abs()) as usize * (height / 2) as usize);
+            EventRef::VideoReceiveFrame(FriendNumber(friend_number), width, height, y, u, v, ystride, ustride, vstride)
+        });
+    }
+}
+
+#[cfg(test)]
+fn local_tox() -> Tox {
+    Tox::new(crate::core::ToxOptions::new().no_lan(), None).unwrap()
+}
+
+#[test]
+fn test_new_av() {
+    let mut tox = local_tox();
+    let mut av = ToxAv::new(&mut tox).unwrap();
+    assert_eq!(av.tick(), Ok(()));
+    assert_eq!(tox.tick(), Ok(()));
+}
+
+#[test]
+// Only one A/V session is allowed per Tox instance
+fn test_new_av_twice() {
+    let mut tox = local_tox();
+    let _av = ToxAv::new(&mut tox).unwrap();
+    assert_eq!(ToxAv::new(&mut tox).err(), Some(errors::NewAvError::Multiple));
+}
+
+#[test]
+// The Tox instance has to outlive the A/V session, whatever the drop order
+fn test_drop_tox_before_av() {
+    let mut tox = local_tox();
+    let mut av = ToxAv::new(&mut tox).unwrap();
+    drop(tox);
+    assert_eq!(av.tick(), Ok(()));
+}
+
+#[test]
+fn test_call_unknown_friend() {
+    let mut tox = local_tox();
+    let mut av = ToxAv::new(&mut tox).unwrap();
+    assert_eq!(av.call(FriendNumber(0), 48, 0), Err(errors::CallError::FriendNotFound));
+}
+
+#[test]
+fn test_av_callback_panic_is_caught() {
+    let mut tox = local_tox();
+    let mut av = ToxAv::new(&mut tox).unwrap();
+    let chan = &mut *av.callbacks as *mut Callbacks as *mut c_void;
+    unsafe {
+        guard(chan, "call", || panic!("boom"));
+        guard(chan, "call_state", || panic!("again"));
     }
+    assert_eq!(av.tick(), Err(CallbackPanic {
+        callback: "call",
+        message: Some("boom".to_owned()),
+    }));
+    assert_eq!(av.tick(), Ok(()));
 }
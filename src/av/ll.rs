@@ -186,7 +186,7 @@ pub type toxav_video_receive_frame_cb =
 
 
 #[link(name = "toxav")]
-extern {
+extern "C" {
     ////////////////////////
     // ToxAV API Version //
     //////////////////////
@@ -1,34 +1,90 @@
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{channel, Receiver};
 use std::{slice, mem, ffi, fmt};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use std::time::Duration;
 use std::thread::sleep;
 use std::str::FromStr;
 use std::mem::MaybeUninit;
+use std::ops::RangeInclusive;
+use std::borrow::Cow;
+use std::panic::{self, AssertUnwindSafe};
 
 use libc::{c_uint, c_void};
 
-// pub use self::ll::Tox as Tox_Struct;
+pub use self::ll::Tox as Tox_Struct;
 pub use self::Event::*;
+pub use self::typing::TypingNotifier;
+pub use self::logging::{LogRecord, ToxLogger};
+pub use self::version::{version, check_compatibility, Version, Incompatibility, BINDINGS_VERSION};
+pub use self::limits::{Limits, truncate_utf8};
+pub use self::handler::{EventHandler, ToxRef};
+pub use self::event_ref::EventRef;
+pub use self::split::{split_utf8, MessageJoiner, PART_MARKER};
+pub use self::delivery::{DeliveryTracker, Delivery, DeliveryState};
+pub use self::outbox::Outbox;
+pub use self::retry::{RetryQueue, Sending};
+pub use self::history::{
+    HistoryStore, FileHistory, History, HistoryEntry, EntryId, Chat, Direction,
+};
+pub use self::ids::{FriendNumber, ConferenceNumber, PeerNumber, FileNumber, MessageId};
+pub use self::handle::ToxHandle;
+pub use self::friend::Friend;
+pub use self::conference::Conference;
+pub use self::envelope::{Envelope, Clock, SystemClock};
+pub use self::queue::{OverflowPolicy, MAX_COALESCED_CHUNK};
+pub use self::subscription::{EventKind, EventFilter};
+#[cfg(feature = "tokio")]
+pub use self::async_tox::{AsyncTox, Events};
 use self::errors::*;
+use self::envelope::Stamper;
+use self::queue::EventQueue;
+use self::subscription::Subscribers;
 
 mod ll;
+mod typing;
+mod logging;
+mod version;
+mod limits;
+mod ids;
+mod handler;
+mod event_ref;
+mod split;
+mod delivery;
+mod outbox;
+mod escape;
+mod retry;
+mod history;
+mod worker;
+mod handle;
+mod friend;
+mod conference;
+mod envelope;
+mod queue;
+mod subscription;
+#[cfg(feature = "tokio")]
+mod async_tox;
 pub mod errors;
 
 pub const PUBLIC_KEY_SIZE:              usize = 32;
 pub const SECRET_KEY_SIZE:              usize = 32;
-pub const ADDRESS_SIZE:                 usize = PUBLIC_KEY_SIZE + 6;
+pub const NOSPAM_SIZE:                  usize = 4;
+pub const ADDRESS_SIZE:                 usize = PUBLIC_KEY_SIZE + NOSPAM_SIZE + 2;
 pub const MAX_NAME_LENGTH:              usize = 128;
-// pub const MAX_STATUSMESSAGE_LENGTH:     usize = 1007;
-// pub const MAX_FRIENDREQUEST_LENGTH:     usize = 1016;
-// pub const MAX_MESSAGE_LENGTH:           usize = 1372;
-// pub const MAX_CUSTOM_PACKET_SIZE:       usize = 1367;
-// pub const HASH_LENGTH:                  usize = 32;
-// pub const FILE_ID_LENGTH:               usize = 32;
-// pub const MAX_FILENAME_LENGTH:          usize = 255;
+pub const MAX_HOSTNAME_LENGTH:          usize = 255;
+pub const MAX_STATUSMESSAGE_LENGTH:     usize = 1007;
+pub const MAX_FRIENDREQUEST_LENGTH:     usize = 1016;
+pub const MAX_MESSAGE_LENGTH:           usize = 1372;
+pub const MAX_CUSTOM_PACKET_SIZE:       usize = 1373;
+pub const HASH_LENGTH:                  usize = 32;
+pub const MAX_FILENAME_LENGTH:          usize = 255;
 pub const CONFERENCE_ID_SIZE:       usize = 32;
+pub const CONFERENCE_UID_SIZE:      usize = 32;
 pub const FILE_ID_LENGTH:           usize = 32;
+/// Packet IDs that toxcore accepts as the first byte of a lossy custom packet.
+pub const LOSSY_PACKET_IDS:         RangeInclusive<u8> = 200..=254;
+/// Packet IDs that toxcore accepts as the first byte of a lossless custom packet.
+pub const LOSSLESS_PACKET_IDS:      RangeInclusive<u8> = 160..=191;
 
 #[repr(u32)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -62,7 +118,7 @@ pub enum SavedataType {
 }
 
 #[repr(u32)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum LogLevel {
     Trace = 0,
     Debug = 1,
@@ -129,10 +185,74 @@ pub enum ConferenceType {
     Av = 1,
 }
 
+/// Persistent identifier of a conference, stable across restarts
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct ConferenceId {
     raw: [u8; CONFERENCE_ID_SIZE]
 }
 
+impl fmt::Display for ConferenceId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for &n in self.raw.iter() {
+            write!(fmt, "{:02X}", n)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ConferenceId {
+    type Err = ();
+    fn from_str(s: &str) -> Result<ConferenceId, ()> {
+        if s.len() != 2 * CONFERENCE_ID_SIZE {
+            return Err(());
+        }
+
+        let mut id = [0u8; CONFERENCE_ID_SIZE];
+
+        parse_hex(s, &mut id[..])?;
+        Ok(ConferenceId { raw: id })
+    }
+}
+
+/// Unique identifier of a conference, stable across restarts
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ConferenceUid {
+    raw: [u8; CONFERENCE_UID_SIZE]
+}
+
+impl fmt::Display for ConferenceUid {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for &n in self.raw.iter() {
+            write!(fmt, "{:02X}", n)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ConferenceUid {
+    type Err = ();
+    fn from_str(s: &str) -> Result<ConferenceUid, ()> {
+        if s.len() != 2 * CONFERENCE_UID_SIZE {
+            return Err(());
+        }
+
+        let mut id = [0u8; CONFERENCE_UID_SIZE];
+
+        parse_hex(s, &mut id[..])?;
+        Ok(ConferenceUid { raw: id })
+    }
+}
+
+#[test]
+fn test_conference_id_hex_round_trip() {
+    let hex = "00112233445566778899AABBCCDDEEFF00112233445566778899AABBCCDDEEFF";
+    let id: ConferenceId = hex.parse().unwrap();
+    let uid: ConferenceUid = hex.to_lowercase().parse().unwrap();
+    assert_eq!(id.to_string(), hex);
+    assert_eq!(uid.to_string(), hex);
+    assert_eq!("00".parse::<ConferenceUid>(), Err(()));
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Cookie {
     raw: Vec<u8>
@@ -238,6 +358,40 @@ fn parse_hex(s: &str, buf: &mut [u8]) -> Result<(),()> {
     return Ok(());
 }
 
+fn check_custom_packet(
+    data: &[u8],
+    ids: RangeInclusive<u8>,
+    max_size: usize
+) -> Result<(), FriendCustomPacketError> {
+    match data.first() {
+        None => Err(FriendCustomPacketError::Empty),
+        Some(_) if data.len() > max_size => Err(FriendCustomPacketError::TooLong),
+        Some(id) if !ids.contains(id) => Err(FriendCustomPacketError::Invalid),
+        Some(_) => Ok(()),
+    }
+}
+
+#[test]
+fn test_custom_packet_ids() {
+    let check = |id, ids| check_custom_packet(&[id, 0], ids, MAX_CUSTOM_PACKET_SIZE);
+    assert_eq!(check(159, LOSSLESS_PACKET_IDS), Err(FriendCustomPacketError::Invalid));
+    assert_eq!(check(160, LOSSLESS_PACKET_IDS), Ok(()));
+    assert_eq!(check(191, LOSSLESS_PACKET_IDS), Ok(()));
+    assert_eq!(check(192, LOSSLESS_PACKET_IDS), Err(FriendCustomPacketError::Invalid));
+    assert_eq!(check(199, LOSSY_PACKET_IDS), Err(FriendCustomPacketError::Invalid));
+    assert_eq!(check(200, LOSSY_PACKET_IDS), Ok(()));
+    assert_eq!(check(254, LOSSY_PACKET_IDS), Ok(()));
+    assert_eq!(check(255, LOSSY_PACKET_IDS), Err(FriendCustomPacketError::Invalid));
+}
+
+#[test]
+fn test_custom_packet_length() {
+    let check = |data: &[u8]| check_custom_packet(data, LOSSY_PACKET_IDS, MAX_CUSTOM_PACKET_SIZE);
+    assert_eq!(check(&[]), Err(FriendCustomPacketError::Empty));
+    assert_eq!(check(&[200; MAX_CUSTOM_PACKET_SIZE]), Ok(()));
+    assert_eq!(check(&[200; MAX_CUSTOM_PACKET_SIZE + 1]), Err(FriendCustomPacketError::TooLong));
+}
+
 /// `PublicKey` is the main part of tox `Address`. Other two are nospam and checksum.
 #[repr(C)]
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
@@ -330,78 +484,78 @@ impl FromStr for Nospam {
 pub enum Event {
     ConnectionStatus(Connection),
     FriendRequest(PublicKey, String),
-    FriendMessage(u32, MessageType, String),
-    FriendName(u32, String),
-    FriendStatusMessage(u32, String),
-    FriendStatus(u32, UserStatus),
-    FriendConnectionStatus(u32, Connection),
-    FriendTyping(u32, bool),
+    FriendMessage(FriendNumber, MessageType, String),
+    FriendName(FriendNumber, String),
+    FriendStatusMessage(FriendNumber, String),
+    FriendStatus(FriendNumber, UserStatus),
+    FriendConnectionStatus(FriendNumber, Connection),
+    FriendTyping(FriendNumber, bool),
     FriendReadReceipt {
-        friend: u32,
-        message_id: u32,
+        friend: FriendNumber,
+        message_id: MessageId,
     },
 
     FileControlReceipt {
-        friend: u32,
-        file_number: u32,
+        friend: FriendNumber,
+        file_number: FileNumber,
         control: FileControl,
     },
     FileChunkRequest {
-        friend: u32,
-        file_number: u32,
+        friend: FriendNumber,
+        file_number: FileNumber,
         position: usize,
         length: usize,
     },
     FileReceipt {
-        friend: u32,
-        file_number: u32,
+        friend: FriendNumber,
+        file_number: FileNumber,
         kind: u32,
         file_size: usize,
         file_name: String,
     },
     FileChunkReceipt {
-        friend: u32,
-        file_number: u32,
+        friend: FriendNumber,
+        file_number: FileNumber,
         position: usize,
         data: Vec<u8>,
     },
 
     ConferenceInvite {
-        friend: u32,
+        friend: FriendNumber,
         kind: ConferenceType,
         cookie: Cookie,
     },
     ConferenceConnected {
-        conference: u32
+        conference: ConferenceNumber
     },
     ConferenceMessage {
-        conference: u32,
-        peer: u32,
+        conference: ConferenceNumber,
+        peer: PeerNumber,
         kind: MessageType,
         message: String,
     },
     ConferenceTitle {
-        conference: u32,
-        peer: u32,
+        conference: ConferenceNumber,
+        peer: PeerNumber,
         title: String,
     },
     ConferencePeerName {
-        conference: u32,
-        peer: u32,
+        conference: ConferenceNumber,
+        peer: PeerNumber,
         name: String,
     },
     ConferencePeerListChanged {
-        conference: u32
+        conference: ConferenceNumber
     },
 
-    LossyPackage(u32, Vec<u8>),
-    LosslessPackage(u32, Vec<u8>),
+    LossyPackage(FriendNumber, Vec<u8>),
+    LosslessPackage(FriendNumber, Vec<u8>),
     /// ToxAV Event
-    Call(u32, bool, bool),
-    CallState(u32, u32),
-    BitRateStatus(u32, u32, u32),
-    AudioReceiveFrame(u32, Vec<i16>, usize, u8, u32),
-    VideoReceiveFrame(u32, u16, u16, Vec<u8>, Vec<u8>, Vec<u8>, i32, i32, i32),
+    Call(FriendNumber, bool, bool),
+    CallState(FriendNumber, u32),
+    BitRateStatus(FriendNumber, u32, u32),
+    AudioReceiveFrame(FriendNumber, Vec<i16>, usize, u8, u32),
+    VideoReceiveFrame(FriendNumber, u16, u16, Vec<u8>, Vec<u8>, Vec<u8>, i32, i32, i32),
 }
 
 // #[repr(C)]
@@ -428,6 +582,10 @@ pub enum Event {
 pub struct ToxOptions {
     raw: ll::Tox_Options,
     sk_ptr: Option<*mut SecretKey>,
+    proxy_host: Option<String>,
+    logger: Option<Box<ToxLogger>>,
+    clock: Option<Box<dyn Clock>>,
+    event_queue: Option<(usize, OverflowPolicy)>,
 }
 
 impl ToxOptions {
@@ -443,6 +601,10 @@ impl ToxOptions {
         ToxOptions {
             raw: raw_options,
             sk_ptr: None,
+            proxy_host: None,
+            logger: None,
+            clock: None,
+            event_queue: None,
         }
     }
 
@@ -477,19 +639,160 @@ impl ToxOptions {
         self
     }
 
-    /*
-    /// Use a proxy
-    pub fn proxy(mut self, ty: ProxyType, addr: &str, port: u16) -> ToxOptions {
-        if addr.len() >= 256 {
-            panic!("proxy address is too long");
-        }
+    /// Disable UDP NAT hole punching
+    pub fn no_hole_punching(mut self) -> ToxOptions {
+        self.raw.hole_punching_enabled = false;
+        self
+    }
+
+    /**
+        Use a proxy.
 
-        self.txo.proxy_address.as_mut_slice()
-        .clone_from_slice(addr.as_bytes());
-        self.txo.proxy_type = ty as u8;
-        self.txo.proxy_port = port;
+        `host` may be an IP address or a DNS name, at most `MAX_HOSTNAME_LENGTH`
+        bytes long. Passing `ProxyType::None` removes a previously set proxy.
+    */
+    pub fn proxy(mut self, ty: ProxyType, host: &str, port: u16) -> ToxOptions {
+        self.raw.proxy_type = ty;
+        self.raw.proxy_port = port;
+        self.proxy_host = match ty {
+            ProxyType::None => None,
+            _ => Some(host.to_owned()),
+        };
         self
-    }*/
+    }
+
+    /**
+        Bind the UDP socket to the first free port in `start..=end`.
+
+        If both ports are 0, toxcore uses its default range 33445-33545. If only
+        one of them is 0, that single port is used. An inverted range is
+        swapped by toxcore.
+    */
+    pub fn udp_port_range(mut self, start: u16, end: u16) -> ToxOptions {
+        self.raw.start_port = start;
+        self.raw.end_port = end;
+        self
+    }
+
+    /// Run a TCP relay server on `port`. 0 disables the server.
+    pub fn tcp_port(mut self, port: u16) -> ToxOptions {
+        self.raw.tcp_port = port;
+        self
+    }
+
+    /**
+        Route the internal logging of toxcore into `logger`.
+
+        The logger is kept alive by the `Tox` instance created from these
+        options.
+    */
+    pub fn with_logger(mut self, logger: ToxLogger) -> ToxOptions {
+        let mut logger = Box::new(logger);
+        self.raw.log_callback = Some(logging::on_log);
+        self.raw.log_user_data = &mut *logger as *mut ToxLogger as *mut c_void;
+        self.logger = Some(logger);
+        self
+    }
+
+    /// Timestamp events with `clock` instead of `SystemClock`
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> ToxOptions {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /**
+        Limit the queue of events read by `Tox::iter()` to `capacity` events.
+
+        `policy` decides what happens once the queue is full, see
+        `OverflowPolicy`: the events of an iteration are always queued or
+        merged, but iterating stops while the queue is over its limit. The
+        queue is unbounded by default.
+
+        Panics if `capacity` is 0.
+    */
+    pub fn event_queue(mut self, capacity: usize, policy: OverflowPolicy) -> ToxOptions {
+        assert!(capacity > 0, "event queue capacity must not be 0");
+        self.event_queue = Some((capacity, policy));
+        self
+    }
+
+    pub fn get_ipv6_enabled(&self) -> bool {
+        self.raw.ipv6_enabled
+    }
+
+    pub fn get_udp_enabled(&self) -> bool {
+        self.raw.udp_enabled
+    }
+
+    pub fn get_local_discovery_enabled(&self) -> bool {
+        self.raw.local_discovery_enabled
+    }
+
+    pub fn get_hole_punching_enabled(&self) -> bool {
+        self.raw.hole_punching_enabled
+    }
+
+    pub fn get_proxy_type(&self) -> ProxyType {
+        self.raw.proxy_type
+    }
+
+    /// Proxy host, or `None` if no proxy is used
+    pub fn get_proxy_host(&self) -> Option<&str> {
+        self.proxy_host.as_deref()
+    }
+
+    pub fn get_proxy_port(&self) -> u16 {
+        self.raw.proxy_port
+    }
+
+    /// The UDP port range as `(start, end)`
+    pub fn get_udp_port_range(&self) -> (u16, u16) {
+        (self.raw.start_port, self.raw.end_port)
+    }
+
+    pub fn get_tcp_port(&self) -> u16 {
+        self.raw.tcp_port
+    }
+
+    /// Capacity and overflow policy of the event queue, `None` if unbounded
+    pub fn get_event_queue(&self) -> Option<(usize, OverflowPolicy)> {
+        self.event_queue
+    }
+
+    /**
+        Check the options for errors that would make `Tox::new` fail.
+
+        Returns `ProxyBadHost` for an empty or too long proxy host or one that
+        contains `\0`, and `ProxyBadPort` for a proxy on port 0.
+    */
+    pub fn validate(&self) -> Result<(), InitError> {
+        if let Some(ref host) = self.proxy_host {
+            if host.is_empty() || host.len() > MAX_HOSTNAME_LENGTH || host.contains('\0') {
+                return Err(InitError::ProxyBadHost);
+            }
+            if self.raw.proxy_port == 0 {
+                return Err(InitError::ProxyBadPort);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_validate_proxy() {
+    let proxy = |host: &str, port| {
+        ToxOptions::new().proxy(ProxyType::Socks5, host, port).validate()
+    };
+    assert_eq!(proxy("127.0.0.1", 9050), Ok(()));
+    assert_eq!(proxy(&"a".repeat(MAX_HOSTNAME_LENGTH), 9050), Ok(()));
+    assert_eq!(proxy("", 9050), Err(InitError::ProxyBadHost));
+    assert_eq!(proxy(&"a".repeat(MAX_HOSTNAME_LENGTH + 1), 9050), Err(InitError::ProxyBadHost));
+    assert_eq!(proxy("local\0host", 9050), Err(InitError::ProxyBadHost));
+    assert_eq!(proxy("127.0.0.1", 0), Err(InitError::ProxyBadPort));
+    // Without a proxy the host and port don't matter
+    assert_eq!(ToxOptions::new().proxy(ProxyType::None, "", 0).validate(), Ok(()));
+    // toxcore swaps an inverted port range
+    assert_eq!(ToxOptions::new().udp_port_range(33545, 33445).validate(), Ok(()));
 }
 
 impl Drop for ToxOptions {
@@ -500,19 +803,27 @@ impl Drop for ToxOptions {
     }
 }
 
-pub struct ToxIter {
-    rx: Rc<RefCell<Receiver<Event>>>,
-}
+// The raw pointers only point into data owned by the options themselves, and
+// the logger and the clock are `Send`
+unsafe impl Send for ToxOptions {}
 
-impl ToxIter {
-    fn new(rx: Rc<RefCell<Receiver<Event>>>) -> ToxIter {
-        ToxIter { rx: rx }
-    }
+pub struct ToxIter {
+    envelopes: Envelopes,
 }
 
 impl Iterator for ToxIter {
     type Item = Event;
-    fn next(&mut self) -> Option<Event> { self.rx.borrow_mut().try_recv().ok() }
+    fn next(&mut self) -> Option<Event> { self.envelopes.next().map(|e| e.event) }
+}
+
+/// Like `ToxIter`, but yields the events in their `Envelope`
+pub struct Envelopes {
+    queue: Rc<RefCell<EventQueue>>,
+}
+
+impl Iterator for Envelopes {
+    type Item = Envelope;
+    fn next(&mut self) -> Option<Envelope> { self.queue.borrow_mut().pop() }
 }
 
 macro_rules! tox_try {
@@ -521,7 +832,7 @@ macro_rules! tox_try {
         let res = $exp;
         match $err.assume_init() as c_uint {
             0 => {},
-            _ => return Err($err.assume_init()),
+            _ => return Err($err.assume_init().into()),
         };
         res
     }};
@@ -539,21 +850,78 @@ macro_rules! tox_option {
     }};
 }
 
-pub struct Tox {
-    pub raw: *mut ll::Tox,
-    pub event_tx: Box<Sender<Event>>,
-    event_rx: Rc<RefCell<Receiver<Event>>>,
+/// Kills the toxcore instance once neither `Tox` nor `ToxAv` uses it
+pub(crate) struct ToxInstance {
+    raw: *mut ll::Tox,
+    // toxcore logs until `tox_kill`, so the logger is dropped after it
+    logger: Option<Box<ToxLogger>>,
+    stamper: Stamper,
+    /// Set while `tox_iterate` or `toxav_iterate` runs
+    iterating: Cell<bool>,
 }
 
-impl Drop for Tox {
+impl ToxInstance {
+    /// Panics if toxcore is already iterating, it isn't reentrant
+    pub(crate) fn begin_iteration(&self) {
+        if self.iterating.replace(true) {
+            panic!("Tox iterated from inside one of its callbacks");
+        }
+    }
+
+    pub(crate) fn end_iteration(&self) {
+        self.iterating.set(false);
+    }
+}
+
+impl Drop for ToxInstance {
     fn drop(&mut self) {
         unsafe { ll::tox_kill(self.raw); }
     }
 }
 
+/// Stamps events and passes them to the subscribers, or queues them for `iter()`
+#[derive(Clone)]
+pub(crate) struct EventSender {
+    queue: Rc<RefCell<EventQueue>>,
+    subscribers: Rc<RefCell<Subscribers>>,
+    pub(crate) instance: Rc<ToxInstance>,
+}
+
+impl EventSender {
+    pub fn send(&self, event: Event) {
+        let envelope = self.instance.stamper.stamp(event);
+        if let Some(envelope) = self.subscribers.borrow_mut().deliver(envelope) {
+            self.queue.borrow_mut().push(envelope);
+        }
+    }
+
+    /// Whether iterating should pause until the queue is drained
+    pub fn blocks(&self) -> bool {
+        self.queue.borrow().blocks()
+    }
+}
+
+pub struct Tox {
+    pub raw: *mut ll::Tox,
+    pub(crate) event_tx: EventSender,
+    pub(crate) instance: Rc<ToxInstance>,
+    limits: Limits,
+}
+
 impl Tox {
     /// Create a new tox instance
+    ///
+    /// Fails with `InitError::Incompatible` if the linked toxcore is not ABI
+    /// compatible with these bindings, saying why, see `check_compatibility()`.
     pub fn new(mut opts: ToxOptions, data: Option<&[u8]>) -> Result<Tox, InitError> {
+        check_compatibility().map_err(InitError::Incompatible)?;
+        opts.validate()?;
+        let c_proxy_host = opts.proxy_host.as_ref()
+            .map(|host| ffi::CString::new(host.as_str()).unwrap());
+        if let Some(ref host) = c_proxy_host {
+            opts.raw.proxy_host = host.as_ptr();
+        }
+
         let tox = unsafe {
             match data {
                 Some(data) => {
@@ -568,9 +936,17 @@ impl Tox {
             }
         };
 
-        let (tx, rx) = channel::<Event>();
-        let event_tx = Box::new(tx);
-        let event_rx = Rc::new(RefCell::new(rx));
+        let instance = Rc::new(ToxInstance {
+            raw: tox,
+            logger: opts.logger.take(),
+            stamper: Stamper::new(opts.clock.take().unwrap_or_else(|| Box::new(SystemClock))),
+            iterating: Cell::new(false),
+        });
+        let event_tx = EventSender {
+            queue: Rc::new(RefCell::new(EventQueue::new(opts.event_queue))),
+            subscribers: Rc::new(RefCell::new(Subscribers::default())),
+            instance: instance.clone(),
+        };
 
         unsafe {
             ll::tox_callback_self_connection_status(tox, Some(on_connection_status));
@@ -606,31 +982,120 @@ impl Tox {
         Ok(Tox {
             raw: tox,
             event_tx,
-            event_rx,
+            instance,
+            limits: Limits::from_library(),
         })
     }
 
+    /// Length limits of the linked toxcore
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
     /// Ticks the Tox and returns an iterator to the Tox events
-    pub fn iter(&mut self) -> ToxIter {
-        self.tick();
-        ToxIter::new(self.event_rx.clone())
+    ///
+    /// Fails if a callback panicked, see `tick()`. The events queued before
+    /// the panic are returned by the next call.
+    pub fn iter(&mut self) -> Result<ToxIter, CallbackPanic> {
+        Ok(ToxIter { envelopes: self.iter_envelopes()? })
+    }
+
+    /// Like `iter()`, but with the time and sequence number of every event
+    pub fn iter_envelopes(&mut self) -> Result<Envelopes, CallbackPanic> {
+        self.tick()?;
+        Ok(Envelopes { queue: self.event_tx.queue.clone() })
+    }
+
+    /**
+        Receive the events matching `filter` on a channel of their own.
+
+        Every event goes to all subscriptions it matches, and is only queued
+        for `iter()` if it matches none of them. A subscription ends when its
+        receiver is dropped. Subscriptions are unbounded, they don't count
+        towards the capacity of `ToxOptions::event_queue`.
+    */
+    pub fn subscribe(&self, filter: EventFilter) -> Receiver<Envelope> {
+        let (tx, rx) = channel();
+        self.event_tx.subscribers.borrow_mut().add(filter, tx);
+        rx
+    }
+
+    /// Number of events dropped or coalesced because the event queue was full
+    pub fn dropped_events(&self) -> u64 {
+        self.event_tx.queue.borrow().dropped()
     }
 
     /// This function animates tox by calling `tox_do()` It function should be called
     /// at least several times per second. Use `wait()` method to get optimal delays
-    pub fn tick(&mut self) {
-        unsafe {
-            let chan = (&mut *self.event_tx) as *mut _ as *mut _;
-            ll::tox_iterate(self.raw, chan);
+    ///
+    /// Does nothing while the event queue is over its limit, see
+    /// `OverflowPolicy`.
+    ///
+    /// Panics in the callbacks are caught instead of unwinding into toxcore.
+    /// The first one is returned once the iteration is over.
+    pub fn tick(&mut self) -> Result<(), CallbackPanic> {
+        if self.event_tx.blocks() {
+            return Ok(());
         }
+        let event_tx = self.event_tx.clone();
+        self.tick_with(&mut |_: &mut ToxRef, event: EventRef| event_tx.send(event.to_event()))
+    }
+
+    /**
+        Like `tick()`, but passes the events to `handler` as they happen
+        instead of queueing them for `iter()`.
+
+        If the handler panics, the iteration goes on with the next event and
+        the first panic is returned at the end. Iterating this `Tox` or its
+        `ToxAv` from the handler panics.
+
+        Strings and data are borrowed from toxcore, a closure taking an
+        `EventRef` gets all events without copying them:
+
+        ```no_run
+        use rstox::core::{Tox, ToxRef, ToxOptions, EventRef};
+
+        let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+        let mut received = 0;
+        tox.tick_with(&mut |_: &mut ToxRef, event: EventRef| {
+            if let EventRef::FileChunkReceipt { data, .. } = event {
+                received += data.len();
+            }
+        }).unwrap();
+        ```
+    */
+    pub fn tick_with(&mut self, handler: &mut dyn EventHandler) -> Result<(), CallbackPanic> {
+        let raw = self.raw;
+        self.iterate_with(handler, |user_data| unsafe { ll::tox_iterate(raw, user_data) })
+    }
+
+    /// Run `iterate` with the `Dispatch` of `handler` as the user data of the callbacks
+    pub(crate) fn iterate_with<F>(
+        &mut self, handler: &mut dyn EventHandler, iterate: F
+    ) -> Result<(), CallbackPanic>
+        where F: FnOnce(*mut c_void)
+    {
+        let instance = self.instance.clone();
+        instance.begin_iteration();
+        let mut dispatch = Dispatch {
+            tox: ToxRef { tox: self },
+            handler,
+            panic: None,
+        };
+        iterate(&mut dispatch as *mut Dispatch as *mut c_void);
+        instance.end_iteration();
+        dispatch.panic.map_or(Ok(()), Err)
     }
 
     /// This function makes thread sleep for a some time, optimal for `tick()` method
     pub fn wait(&self) {
-        unsafe {
-            let delay = ll::tox_iteration_interval(self.raw);
-            sleep(Duration::from_millis(delay as u64));
-        }
+        sleep(self.iteration_interval());
+    }
+
+    /// The time toxcore wants to pass until the next `tick()`
+    pub fn iteration_interval(&self) -> Duration {
+        let delay = unsafe { ll::tox_iteration_interval(self.raw) };
+        Duration::from_millis(delay as u64)
     }
 
     /**
@@ -649,6 +1114,9 @@ impl Tox {
         Panics if `host` string contains `\0`.
     */
     pub fn bootstrap(&mut self, host: &str, port: u16, public_key: PublicKey) -> Result<(), BootstrapError> {
+        if host.len() > self.limits.max_hostname_length {
+            return Err(BootstrapError::BadHost);
+        }
         unsafe {
             let c_host = ffi::CString::new(host).unwrap();
             let c_pk: *const u8 = &public_key as *const _ as *const _;
@@ -657,6 +1125,66 @@ impl Tox {
         Ok(())
     }
 
+    /**
+        Adds additional host:port pair as TCP relay.
+
+        This function can be used to initiate TCP connections to different ports
+        on the same bootstrap node, or to add TCP relays without using them as
+        bootstrap nodes.
+
+        ## Panics
+        Panics if `host` string contains `\0`.
+    */
+    pub fn add_tcp_relay(&mut self, host: &str, port: u16, public_key: PublicKey) -> Result<(), BootstrapError> {
+        if host.len() > self.limits.max_hostname_length {
+            return Err(BootstrapError::BadHost);
+        }
+        unsafe {
+            let c_host = ffi::CString::new(host).unwrap();
+            let c_pk: *const u8 = &public_key as *const _ as *const _;
+            tox_try!(err, ll::tox_add_tcp_relay(self.raw, c_host.as_ptr(), port, c_pk, err.as_mut_ptr()));
+        }
+        Ok(())
+    }
+
+    /**
+        Get the temporary DHT public key of this instance.
+
+        This can be used in combination with an externally accessible IP
+        address and the bound port (from `get_udp_port`) to run a temporary
+        bootstrap node. Be aware that the key changes every time the Tox
+        instance is created.
+    */
+    pub fn get_dht_id(&self) -> PublicKey {
+        unsafe {
+            let mut pk = MaybeUninit::<[u8; PUBLIC_KEY_SIZE]>::uninit();
+            ll::tox_self_get_dht_id(self.raw, pk.as_mut_ptr() as *mut u8);
+            PublicKey {
+                raw: pk.assume_init()
+            }
+        }
+    }
+
+    /// Return the UDP port this Tox instance is bound to
+    pub fn get_udp_port(&self) -> Result<u16, GetPortError> {
+        unsafe {
+            let port = tox_try!(err, ll::tox_self_get_udp_port(self.raw, err.as_mut_ptr()));
+            Ok(port)
+        }
+    }
+
+    /**
+        Return the TCP port this Tox instance is bound to. This is only
+        relevant if the instance is acting as a TCP relay, i.e. a `tcp_port`
+        was set in the options.
+    */
+    pub fn get_tcp_port(&self) -> Result<u16, GetPortError> {
+        unsafe {
+            let port = tox_try!(err, ll::tox_self_get_tcp_port(self.raw, err.as_mut_ptr()));
+            Ok(port)
+        }
+    }
+
     /// Get self connection status
     pub fn get_connection_status(&self) -> Connection {
         unsafe { ll::tox_self_get_connection_status(self.raw) }
@@ -710,6 +1238,9 @@ impl Tox {
 
     /// Set the nickname for the Tox client
     pub fn set_name(&mut self, name: &str) -> Result<(), SetInfoError> {
+        if name.len() > self.limits.max_name_length {
+            return Err(SetInfoError::TooLong);
+        }
         unsafe {
             tox_try!(err, ll::tox_self_set_name(self.raw, name.as_ptr(), name.len(), err.as_mut_ptr()));
         }
@@ -729,6 +1260,9 @@ impl Tox {
 
     /// Set self status message
     pub fn set_status_message(&mut self, message: &str) -> Result<(), SetInfoError> {
+        if message.len() > self.limits.max_status_message_length {
+            return Err(SetInfoError::TooLong);
+        }
         unsafe {
             tox_try!(err, ll::tox_self_set_status_message(self.raw, message.as_ptr(), message.len(), err.as_mut_ptr()));
         }
@@ -771,6 +1305,9 @@ impl Tox {
         numbers should not be relied on.
     */
     pub fn add_friend(&mut self, address: &Address, message: &str) -> Result<(), FriendAddError> {
+        if message.len() > self.limits.max_friend_request_length {
+            return Err(FriendAddError::TooLong);
+        }
         unsafe {
             let c_addr = address as *const _ as *const u8;
             tox_try!(
@@ -808,10 +1345,10 @@ impl Tox {
         function, this client will appear offline to the friend and no communication
         can occur between the two.
     */
-    pub fn delete_friend(&mut self, fnum: u32) -> Result<(), ()> {
+    pub fn delete_friend(&mut self, fnum: FriendNumber) -> Result<(), ()> {
         unsafe {
             let mut err = MaybeUninit::uninit();
-            if !ll::tox_friend_delete(self.raw, fnum, err.as_mut_ptr()) {
+            if !ll::tox_friend_delete(self.raw, fnum.0, err.as_mut_ptr()) {
                 return Err(())
             }
         }
@@ -819,35 +1356,49 @@ impl Tox {
     }
 
     // FRIEND STUFF
-    pub fn friend_by_public_key(&self, public_key: PublicKey) -> Option<u32> {
+    pub fn friend_by_public_key(&self, public_key: PublicKey) -> Option<FriendNumber> {
         unsafe {
             let pk: *const u8 = &public_key as *const _ as *const _;
             let fnum = tox_option!(err, ll::tox_friend_by_public_key(self.raw, pk, err.as_mut_ptr()));
-            Some(fnum)
+            Some(FriendNumber(fnum))
         }
     }
 
-    pub fn friend_exists(&self, fnum: u32) -> bool {
+    pub fn friend_exists(&self, fnum: FriendNumber) -> bool {
         unsafe {
-            ll::tox_friend_exists(self.raw, fnum)
+            ll::tox_friend_exists(self.raw, fnum.0)
         }
     }
 
-    pub fn get_friend_list(&self) -> Vec<u32> {
+    pub fn get_friend_list(&self) -> Vec<FriendNumber> {
         unsafe {
             let len = ll::tox_self_get_friend_list_size(self.raw);
-            let mut list = Vec::with_capacity(len);
-            list.set_len(len);
-            ll::tox_self_get_friend_list(self.raw, list.as_mut_ptr());
+            let mut list = vec![FriendNumber(0); len];
+            ll::tox_self_get_friend_list(self.raw, list.as_mut_ptr() as *mut u32);
             list
         }
     }
 
-    pub fn get_friend_public_key(&self, fnum: u32) -> Option<PublicKey> {
+    /// The friend with number `fnum`, or `None` if there is no such friend
+    pub fn friend(&self, fnum: FriendNumber) -> Option<Friend<&Tox>> {
+        Friend::new(self, fnum)
+    }
+
+    /// Like `friend()`, but the handle can also send to and delete the friend
+    pub fn friend_mut(&mut self, fnum: FriendNumber) -> Option<Friend<&mut Tox>> {
+        Friend::new(self, fnum)
+    }
+
+    /// All friends in the friend list
+    pub fn friends(&self) -> impl Iterator<Item = Friend<&Tox>> {
+        self.get_friend_list().into_iter().filter_map(move |fnum| Friend::new(self, fnum))
+    }
+
+    pub fn get_friend_public_key(&self, fnum: FriendNumber) -> Option<PublicKey> {
         unsafe {
             let mut public_key = MaybeUninit::uninit();
             let pk: *mut u8 = &mut public_key as *mut _ as *mut _;
-            tox_option!(err, ll::tox_friend_get_public_key(self.raw, fnum, pk, err.as_mut_ptr()));
+            tox_option!(err, ll::tox_friend_get_public_key(self.raw, fnum.0, pk, err.as_mut_ptr()));
             Some(public_key.assume_init())
         }
     }
@@ -859,51 +1410,77 @@ impl Tox {
         In case where there is no friend with supplied `fnum`, `None` is
         returned.
     */
-    pub fn get_friend_last_online(&self, fnum: u32) -> Option<u64> {
+    pub fn get_friend_last_online(&self, fnum: FriendNumber) -> Option<u64> {
         unsafe {
-            Some(tox_option!(err, ll::tox_friend_get_last_online(self.raw, fnum, err.as_mut_ptr())))
+            Some(tox_option!(err, ll::tox_friend_get_last_online(self.raw, fnum.0, err.as_mut_ptr())))
         }
     }
 
     /// Returns friend name, or, if friend doesn't exist, `None`.
-    pub fn get_friend_name(&self, fnum: u32) -> Option<String> {
+    pub fn get_friend_name(&self, fnum: FriendNumber) -> Option<String> {
         unsafe {
             let len = tox_option!(err, ll::tox_friend_get_name_size(self.raw,
-                                fnum, err.as_mut_ptr()));
+                                fnum.0, err.as_mut_ptr()));
             let mut bytes: Vec<u8> = Vec::with_capacity(len);
             bytes.set_len(len);
-            tox_option!(err, ll::tox_friend_get_name(self.raw, fnum,
+            tox_option!(err, ll::tox_friend_get_name(self.raw, fnum.0,
                     bytes.as_mut_ptr(), err.as_mut_ptr()));
             Some(String::from_utf8_unchecked(bytes))
         }
     }
 
     /// Returns status message of a friend, or, if friend doesn't exist, `None`.
-    pub fn get_friend_status_message(&self, fnum: u32) -> Option<String> {
+    pub fn get_friend_status_message(&self, fnum: FriendNumber) -> Option<String> {
         unsafe {
             let len = tox_option!(err, ll::tox_friend_get_status_message_size(self.raw,
-                                fnum, err.as_mut_ptr()));
+                                fnum.0, err.as_mut_ptr()));
             let mut bytes: Vec<u8> = Vec::with_capacity(len);
             bytes.set_len(len);
-            tox_option!(err, ll::tox_friend_get_status_message(self.raw, fnum,
+            tox_option!(err, ll::tox_friend_get_status_message(self.raw, fnum.0,
                     bytes.as_mut_ptr(), err.as_mut_ptr()));
             Some(String::from_utf8_unchecked(bytes))
         }
     }
 
     /// Returns friend status, or, if there is an error, `None`.
-    pub fn get_friend_status(&self, fnum: u32) -> Option<UserStatus> {
+    pub fn get_friend_status(&self, fnum: FriendNumber) -> Option<UserStatus> {
         unsafe {
-            Some(tox_option!(err, ll::tox_friend_get_status(self.raw, fnum, err.as_mut_ptr())))
+            Some(tox_option!(err, ll::tox_friend_get_status(self.raw, fnum.0, err.as_mut_ptr())))
         }
     }
 
     /// Return status of connection of friend, or if friend doesn't exist, `None`.
-    pub fn get_friend_connection_status(&self, fnum: u32) -> Option<Connection> {
+    pub fn get_friend_connection_status(&self, fnum: FriendNumber) -> Option<Connection> {
         unsafe {
-            Some(tox_option!(err, ll::tox_friend_get_connection_status(self.raw, fnum, err.as_mut_ptr())))
+            Some(tox_option!(err, ll::tox_friend_get_connection_status(self.raw, fnum.0, err.as_mut_ptr())))
         }
     }
+
+    /**
+        Returns `true` if the friend is typing, `false` otherwise.
+
+        The result of this function is equal to the last value received by the
+        `FriendTyping` event.
+    */
+    pub fn get_friend_typing(&self, fnum: FriendNumber) -> Result<bool, FriendQueryError> {
+        unsafe {
+            let is_typing = tox_try!(err, ll::tox_friend_get_typing(self.raw, fnum.0, err.as_mut_ptr()));
+            Ok(is_typing)
+        }
+    }
+
+    /**
+        Set the client's typing status for a friend.
+
+        The client is responsible for turning it on or off. See `TypingNotifier`
+        for a helper that does this based on keystrokes.
+    */
+    pub fn set_typing(&mut self, fnum: FriendNumber, is_typing: bool) -> Result<(), SetTypingError> {
+        unsafe {
+            tox_try!(err, ll::tox_self_set_typing(self.raw, fnum.0, is_typing, err.as_mut_ptr()));
+        }
+        Ok(())
+    }
     // END OF FRIEND STUFF
     /**
         Send a text chat message to an online friend.
@@ -923,28 +1500,52 @@ impl Tox {
         sent, the next message ID is 0.
     */
     pub fn send_friend_message(
-        &mut self, fnum: u32, kind: MessageType, message: &str
-    ) -> Result<u32, FriendSendMessageError> {
+        &mut self, fnum: FriendNumber, kind: MessageType, message: &str
+    ) -> Result<MessageId, FriendSendMessageError> {
+        if message.len() > self.limits.max_message_length {
+            return Err(FriendSendMessageError::TooLong);
+        }
         let msg_id = unsafe {
             tox_try!(
                 err,
-                ll::tox_friend_send_message(self.raw, fnum, kind, message.as_ptr(), message.len(), err.as_mut_ptr())
+                ll::tox_friend_send_message(self.raw, fnum.0, kind, message.as_ptr(), message.len(), err.as_mut_ptr())
             )
         };
-        Ok(msg_id)
+        Ok(MessageId(msg_id))
+    }
+
+    /**
+        Send `message` in as many messages as needed to fit
+        `Limits::max_message_length`, see `Limits::split_message`. Returns the
+        ids of all parts; `MessageJoiner` puts them back together on the other
+        end.
+
+        Stops at the first part that can't be sent.
+    */
+    pub fn send_friend_message_split(
+        &mut self, fnum: FriendNumber, kind: MessageType, message: &str
+    ) -> Result<Vec<MessageId>, FriendSendSplitError> {
+        let mut sent = vec![];
+        for part in self.limits.split_message(message) {
+            match self.send_friend_message(fnum, kind, &part) {
+                Ok(id) => sent.push(id),
+                Err(error) => return Err(FriendSendSplitError { sent, error }),
+            }
+        }
+        Ok(sent)
     }
 
     pub fn control_file(
         &mut self,
-        friend: u32,
-        file_number: u32,
+        friend: FriendNumber,
+        file_number: FileNumber,
         control: FileControl
     ) -> Result<(), FileControlError> {
         unsafe {
             tox_try!(err, ll::tox_file_control(
                 self.raw,
-                friend,
-                file_number,
+                friend.0,
+                file_number.0,
                 control,
                 err.as_mut_ptr()
             ));
@@ -955,15 +1556,15 @@ impl Tox {
 
     pub fn seek_file(
         &mut self,
-        friend: u32,
-        file_number: u32,
+        friend: FriendNumber,
+        file_number: FileNumber,
         postition: usize
     ) -> Result<(), FileSeekError> {
         unsafe {
             tox_try!(err, ll::tox_file_seek(
                 self.raw,
-                friend,
-                file_number,
+                friend.0,
+                file_number.0,
                 postition as u64,
                 err.as_mut_ptr()
             ));
@@ -974,16 +1575,16 @@ impl Tox {
 
     pub fn get_file_id(
         &mut self,
-        friend: u32,
-        file_number: u32,
+        friend: FriendNumber,
+        file_number: FileNumber,
     ) -> Result<FileId, FileGetError> {
         unsafe {
             let mut raw = MaybeUninit::<[u8; FILE_ID_LENGTH]>::uninit();
 
             tox_try!(err, ll::tox_file_get_file_id(
                 self.raw,
-                friend,
-                file_number,
+                friend.0,
+                file_number.0,
                 raw.as_mut_ptr() as *mut u8,
                 err.as_mut_ptr()
             ));
@@ -996,15 +1597,18 @@ impl Tox {
 
     pub fn send_file(
         &mut self,
-        friend: u32,
+        friend: FriendNumber,
         kind: FileKind,
         file_size: usize,
         file_name: &str,
-    ) -> Result<u32, FileSendError> {
+    ) -> Result<FileNumber, FileSendError> {
+        if file_name.len() > self.limits.max_filename_length {
+            return Err(FileSendError::NameTooLong);
+        }
         unsafe {
             let file_number = tox_try!(err, ll::tox_file_send(
                 self.raw,
-                friend,
+                friend.0,
                 kind as u32,
                 file_size as u64,
                 std::ptr::null(),
@@ -1013,22 +1617,25 @@ impl Tox {
                 err.as_mut_ptr()
             ));
 
-            Ok(file_number)
+            Ok(FileNumber(file_number))
         }
     }
 
     pub fn send_file_with_id(
         &mut self,
-        friend: u32,
+        friend: FriendNumber,
         kind: FileKind,
         file_size: usize,
         file_id: FileId,
         file_name: &str,
-    ) -> Result<u32, FileSendError> {
+    ) -> Result<FileNumber, FileSendError> {
+        if file_name.len() > self.limits.max_filename_length {
+            return Err(FileSendError::NameTooLong);
+        }
         unsafe {
             let file_number = tox_try!(err, ll::tox_file_send(
                 self.raw,
-                friend,
+                friend.0,
                 kind as u32,
                 file_size as u64,
                 file_id.raw.as_ptr(),
@@ -1037,22 +1644,22 @@ impl Tox {
                 err.as_mut_ptr()
             ));
 
-            Ok(file_number)
+            Ok(FileNumber(file_number))
         }
     }
 
     pub fn send_file_chunk(
         &mut self,
-        friend: u32,
-        file_number: u32,
+        friend: FriendNumber,
+        file_number: FileNumber,
         position: usize,
         data: &[u8]
     ) -> Result<(), FileSendChunkError> {
         unsafe {
             tox_try!(err, ll::tox_file_send_chunk(
                 self.raw,
-                friend,
-                file_number,
+                friend.0,
+                file_number.0,
                 position as u64,
                 data.as_ptr(),
                 data.len(),
@@ -1063,25 +1670,85 @@ impl Tox {
         }
     }
 
+    /**
+        Send a custom lossy packet to a friend.
+
+        The first byte of data must be in the range 200-254. Maximum length of a
+        custom packet is `MAX_CUSTOM_PACKET_SIZE`.
+
+        Lossy packets behave like UDP packets, meaning they might never reach the
+        other side or might arrive more than once (if someone is messing with the
+        connection) or might arrive in the wrong order.
+
+        Unless latency is an issue, it is recommended that you use lossless custom
+        packets instead.
+    */
+    pub fn send_lossy_packet(
+        &mut self,
+        friend: FriendNumber,
+        data: &[u8]
+    ) -> Result<(), FriendCustomPacketError> {
+        check_custom_packet(data, LOSSY_PACKET_IDS, self.limits.max_custom_packet_size)?;
+        unsafe {
+            tox_try!(err, ll::tox_friend_send_lossy_packet(
+                self.raw,
+                friend.0,
+                data.as_ptr(),
+                data.len(),
+                err.as_mut_ptr()
+            ));
+
+            Ok(())
+        }
+    }
+
+    /**
+        Send a custom lossless packet to a friend.
+
+        The first byte of data must be in the range 160-191. Maximum length of a
+        custom packet is `MAX_CUSTOM_PACKET_SIZE`.
+
+        Lossless packet behaviour is comparable to TCP (reliability, arrive in
+        order) but with packets instead of a stream.
+    */
+    pub fn send_lossless_packet(
+        &mut self,
+        friend: FriendNumber,
+        data: &[u8]
+    ) -> Result<(), FriendCustomPacketError> {
+        check_custom_packet(data, LOSSLESS_PACKET_IDS, self.limits.max_custom_packet_size)?;
+        unsafe {
+            tox_try!(err, ll::tox_friend_send_lossless_packet(
+                self.raw,
+                friend.0,
+                data.as_ptr(),
+                data.len(),
+                err.as_mut_ptr()
+            ));
+
+            Ok(())
+        }
+    }
+
     // Conference stuff
 
-    pub fn new_conference(&mut self) -> Result<u32, ()> {
+    pub fn new_conference(&mut self) -> Result<ConferenceNumber, ()> {
         unsafe {
             let mut err = MaybeUninit::uninit();
             let res = ll::tox_conference_new(self.raw, err.as_mut_ptr());
 
             match err.assume_init() as c_uint {
-                0 => return Ok(res),
+                0 => return Ok(ConferenceNumber(res)),
                 _ => return Err(()),
             };
         }
     }
 
-    pub fn delete_conference(&mut self, conference_number: u32) -> Option<()> {
+    pub fn delete_conference(&mut self, conference_number: ConferenceNumber) -> Option<()> {
         unsafe {
             tox_option!(err, ll::tox_conference_delete(
                 self.raw,
-                conference_number,
+                conference_number.0,
                 err.as_mut_ptr()
             ));
             Some(())
@@ -1089,12 +1756,12 @@ impl Tox {
     }
 
     pub fn conference_peer_count(
-        &mut self, conference_number: u32
+        &self, conference_number: ConferenceNumber
     )-> Result<u32, ConferencePeerQueryError> {
         unsafe {
             let count = tox_try!(err, ll::tox_conference_peer_count(
-                self.raw(),
-                conference_number,
+                self.raw,
+                conference_number.0,
                 err.as_mut_ptr()
             ));
 
@@ -1103,15 +1770,15 @@ impl Tox {
     }
 
     pub fn get_peer_name(
-        &mut self,
-        conference_number: u32,
-        peer_number: u32
+        &self,
+        conference_number: ConferenceNumber,
+        peer_number: PeerNumber
     ) -> Result<String, ConferencePeerQueryError> {
         unsafe {
             let size = tox_try!(err, ll::tox_conference_peer_get_name_size(
                 self.raw,
-                conference_number,
-                peer_number,
+                conference_number.0,
+                peer_number.0,
                 err.as_mut_ptr()
             ));
 
@@ -1119,8 +1786,8 @@ impl Tox {
 
             tox_try!(err, ll::tox_conference_peer_get_name(
                 self.raw,
-                conference_number,
-                peer_number,
+                conference_number.0,
+                peer_number.0,
                 raw.as_mut_ptr() as *mut u8,
                 err.as_mut_ptr()
             ));
@@ -1131,16 +1798,16 @@ impl Tox {
     }
 
     pub fn get_peer_public_key(
-        &mut self,
-        conference_number: u32,
-        peer_number: u32,
+        &self,
+        conference_number: ConferenceNumber,
+        peer_number: PeerNumber,
     ) -> Result<PublicKey, ConferencePeerQueryError> {
         unsafe {
             let mut raw = MaybeUninit::<[u8; PUBLIC_KEY_SIZE]>::uninit();
             tox_try!(err, ll::tox_conference_peer_get_public_key(
                 self.raw,
-                conference_number,
-                peer_number,
+                conference_number.0,
+                peer_number.0,
                 raw.as_mut_ptr() as *mut u8,
                 err.as_mut_ptr()
             ));
@@ -1152,16 +1819,16 @@ impl Tox {
     }
 
     pub fn is_own_peer_number(
-        &mut self,
-        conference_number: u32,
-        peer_number: u32
+        &self,
+        conference_number: ConferenceNumber,
+        peer_number: PeerNumber
     ) -> Result<bool, ConferencePeerQueryError> {
         unsafe {
             let is_ours =
                 tox_try!(err, ll::tox_conference_peer_number_is_ours(
                     self.raw,
-                    conference_number,
-                    peer_number,
+                    conference_number.0,
+                    peer_number.0,
                     err.as_mut_ptr()
                 ));
 
@@ -1171,12 +1838,12 @@ impl Tox {
 
     pub fn conference_offline_peer_count(
         &mut self,
-        conference_number: u32
+        conference_number: ConferenceNumber
     ) -> Result<u32, ConferencePeerQueryError> {
         unsafe {
             let count = tox_try!(err, ll::tox_conference_offline_peer_count(
                 self.raw,
-                conference_number,
+                conference_number.0,
                 err.as_mut_ptr()
             ));
 
@@ -1186,14 +1853,14 @@ impl Tox {
 
     pub fn get_offline_peer_name(
         &mut self,
-        conference_number: u32,
-        peer_number: u32,
+        conference_number: ConferenceNumber,
+        peer_number: PeerNumber,
     ) -> Result<String, ConferencePeerQueryError> {
         unsafe {
             let size = tox_try!(err, ll::tox_conference_offline_peer_get_name_size(
                 self.raw,
-                conference_number,
-                peer_number,
+                conference_number.0,
+                peer_number.0,
                 err.as_mut_ptr()
             ));
 
@@ -1201,8 +1868,8 @@ impl Tox {
 
             tox_try!(err, ll::tox_conference_offline_peer_get_name(
                 self.raw,
-                conference_number,
-                peer_number,
+                conference_number.0,
+                peer_number.0,
                 raw.as_mut_ptr() as *mut u8,
                 err.as_mut_ptr()
             ));
@@ -1213,16 +1880,16 @@ impl Tox {
 
     pub fn get_offline_peer_public_key(
         &mut self,
-        conference_number: u32,
-        peer_number: u32
+        conference_number: ConferenceNumber,
+        peer_number: PeerNumber
     ) -> Result<PublicKey, ConferencePeerQueryError> {
         unsafe {
             let mut raw = MaybeUninit::<[u8; PUBLIC_KEY_SIZE]>::uninit();
 
             tox_try!(err, ll::tox_conference_peer_get_public_key(
                 self.raw,
-                conference_number,
-                peer_number,
+                conference_number.0,
+                peer_number.0,
                 raw.as_mut_ptr() as *mut u8,
                 err.as_mut_ptr()
             ));
@@ -1235,14 +1902,14 @@ impl Tox {
 
     pub fn get_offline_peer_last_active(
         &mut self,
-        conference_number: u32,
-        peer_number: u32,
+        conference_number: ConferenceNumber,
+        peer_number: PeerNumber,
     ) -> Result<u64, ConferencePeerQueryError> {
         unsafe {
             let time = tox_try!(err, ll::tox_conference_offline_peer_get_last_active(
                 self.raw,
-                conference_number,
-                peer_number,
+                conference_number.0,
+                peer_number.0,
                 err.as_mut_ptr()
             ));
 
@@ -1252,14 +1919,14 @@ impl Tox {
 
     pub fn invite_to_conference(
         &mut self,
-        friend_number: u32,
-        conference_number: u32
+        friend_number: FriendNumber,
+        conference_number: ConferenceNumber
     ) -> Result<(), ConferenceInviteError> {
         unsafe {
             tox_try!(err, ll::tox_conference_invite(
                 self.raw,
-                friend_number,
-                conference_number,
+                friend_number.0,
+                conference_number.0,
                 err.as_mut_ptr()
             ));
 
@@ -1269,35 +1936,38 @@ impl Tox {
 
     pub fn join_conference(
         &mut self,
-        friend_number: u32,
+        friend_number: FriendNumber,
         cookie: &Cookie,
-    ) -> Result<u32, ConferenceJoinError> {
+    ) -> Result<ConferenceNumber, ConferenceJoinError> {
         unsafe {
             let conference = tox_try!(err, ll::tox_conference_join(
                 self.raw,
-                friend_number,
+                friend_number.0,
                 cookie.raw.as_ptr(),
                 cookie.raw.len(),
                 err.as_mut_ptr()
             ));
 
-            Ok(conference)
+            Ok(ConferenceNumber(conference))
         }
     }
 
     pub fn send_conference_message(
         &mut self,
-        conference_number: u32,
+        conference_number: ConferenceNumber,
         kind: MessageType,
         message: &str
     ) -> Result<(), ConferenceSendError> {
+        if message.len() > self.limits.max_message_length {
+            return Err(ConferenceSendError::TooLong);
+        }
         unsafe {
             let msg = message.as_ptr();
             let len = message.len();
 
             tox_try!(err, ll::tox_conference_send_message(
                 self.raw,
-                conference_number,
+                conference_number.0,
                 kind,
                 msg,
                 len,
@@ -1308,14 +1978,30 @@ impl Tox {
         }
     }
 
-    pub fn get_conference_title(
+    /// Like `send_friend_message_split`, for a conference. Returns the number
+    /// of parts.
+    pub fn send_conference_message_split(
         &mut self,
-        conference_number: u32
+        conference_number: ConferenceNumber,
+        kind: MessageType,
+        message: &str
+    ) -> Result<usize, ConferenceSendSplitError> {
+        let parts = self.limits.split_message(message);
+        for (sent, part) in parts.iter().enumerate() {
+            self.send_conference_message(conference_number, kind, part)
+                .map_err(|error| ConferenceSendSplitError { sent, error })?;
+        }
+        Ok(parts.len())
+    }
+
+    pub fn get_conference_title(
+        &self,
+        conference_number: ConferenceNumber
     ) -> Result<String, ConferenceTitleError> {
         unsafe {
             let size = tox_try!(err, ll::tox_conference_get_title_size(
                 self.raw,
-                conference_number,
+                conference_number.0,
                 err.as_mut_ptr()
             ));
 
@@ -1323,7 +2009,7 @@ impl Tox {
 
             tox_try!(err, ll::tox_conference_get_title(
                 self.raw,
-                conference_number,
+                conference_number.0,
                 raw.as_mut_ptr() as *mut u8,
                 err.as_mut_ptr()
             ));
@@ -1334,15 +2020,18 @@ impl Tox {
 
     pub fn set_conference_title(
         &mut self,
-        conference_number: u32,
+        conference_number: ConferenceNumber,
         title: &str
     ) -> Result<(), ConferenceTitleError> {
+        if title.len() > self.limits.max_name_length {
+            return Err(ConferenceTitleError::InvalidLength);
+        }
         unsafe {
             let len = title.len();
 
             tox_try!(err, ll::tox_conference_set_title(
                 self.raw,
-                conference_number,
+                conference_number.0,
                 title.as_ptr(),
                 len,
                 err.as_mut_ptr()
@@ -1352,31 +2041,50 @@ impl Tox {
         }
     }
 
-    pub fn get_chatlist(&mut self) -> Vec<u32> {
+    pub fn get_chatlist(&self) -> Vec<ConferenceNumber> {
         unsafe {
             let len = ll::tox_conference_get_chatlist_size(
                 self.raw
             );
 
-            let mut chatlist = Vec::with_capacity(len);
+            let mut chatlist = vec![ConferenceNumber(0); len];
 
             ll::tox_conference_get_chatlist(
                 self.raw,
-                chatlist.as_mut_ptr()
+                chatlist.as_mut_ptr() as *mut u32
             );
 
             chatlist
         }
     }
 
+    /// The conference with number `conference_number`, or `None` if there is no
+    /// such conference
+    pub fn conference(&self, conference_number: ConferenceNumber) -> Option<Conference<&Tox>> {
+        Conference::new(self, conference_number)
+    }
+
+    /// Like `conference()`, but the handle can also send messages, invite
+    /// friends, change the title and leave
+    pub fn conference_mut(
+        &mut self, conference_number: ConferenceNumber
+    ) -> Option<Conference<&mut Tox>> {
+        Conference::new(self, conference_number)
+    }
+
+    /// All conferences we are in
+    pub fn conferences(&self) -> impl Iterator<Item = Conference<&Tox>> {
+        self.get_chatlist().into_iter().filter_map(move |number| Conference::new(self, number))
+    }
+
     pub fn get_conference_type(
-        &mut self,
-        conference_number: u32
+        &self,
+        conference_number: ConferenceNumber
     ) -> Option<ConferenceType> {
         unsafe {
             let kind = tox_option!(err, ll::tox_conference_get_type(
                 self.raw,
-                conference_number,
+                conference_number.0,
                 err.as_mut_ptr()
             ));
 
@@ -1385,15 +2093,15 @@ impl Tox {
     }
 
     pub fn get_conference_id(
-        &mut self,
-        conference_number: u32
+        &self,
+        conference_number: ConferenceNumber
     ) -> Option<ConferenceId> {
         unsafe {
             let mut raw = [0; CONFERENCE_ID_SIZE];
 
             let exists = ll::tox_conference_get_id(
                 self.raw,
-                conference_number,
+                conference_number.0,
                 raw.as_mut_ptr(),
             );
 
@@ -1411,7 +2119,7 @@ impl Tox {
     pub fn conference_by_id(
         &mut self,
         id: &ConferenceId
-    ) -> Option<u32> {
+    ) -> Option<ConferenceNumber> {
         unsafe {
             let conf_num = tox_option!(err, ll::tox_conference_by_id(
                 self.raw,
@@ -1419,7 +2127,48 @@ impl Tox {
                 err.as_mut_ptr()
             ));
 
-            Some(conf_num)
+            Some(ConferenceNumber(conf_num))
+        }
+    }
+
+    /// Get the unique identifier of a conference, or `None` if it doesn't exist
+    pub fn get_conference_uid(
+        &self,
+        conference_number: ConferenceNumber
+    ) -> Option<ConferenceUid> {
+        unsafe {
+            let mut raw = [0; CONFERENCE_UID_SIZE];
+
+            let exists = ll::tox_conference_get_uid(
+                self.raw,
+                conference_number.0,
+                raw.as_mut_ptr(),
+            );
+
+            if exists {
+                Some(ConferenceUid{
+                    raw,
+                })
+            }
+            else {
+                None
+            }
+        }
+    }
+
+    /// Find the conference number of a conference by its unique identifier
+    pub fn conference_by_uid(
+        &mut self,
+        uid: &ConferenceUid
+    ) -> Option<ConferenceNumber> {
+        unsafe {
+            let conf_num = tox_option!(err, ll::tox_conference_by_uid(
+                self.raw,
+                uid.raw.as_ptr(),
+                err.as_mut_ptr()
+            ));
+
+            Some(ConferenceNumber(conf_num))
         }
     }
 
@@ -1434,14 +2183,6 @@ impl Tox {
         }
     }
 
-    #[inline]
-    #[doc(hidden)]
-    pub unsafe fn from_raw_tox(raw: *mut ll::Tox) -> Tox {
-        let mut tox = MaybeUninit::<Tox>::uninit();
-        (*tox.as_mut_ptr()).raw = raw;
-        tox.assume_init()
-    }
-
     #[inline]
     #[doc(hidden)]
     pub unsafe fn raw(&mut self) -> *mut ll::Tox {
@@ -1451,122 +2192,164 @@ impl Tox {
 
 // BEGIN: Callback pack
 
-extern fn on_connection_status(_: *mut ll::Tox, status: Connection, chan: *mut c_void) {
-    unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        tx.send(ConnectionStatus(status)).unwrap();
+/// What `tox_iterate` gets as user data: the `Tox` and the handler to call
+struct Dispatch<'a> {
+    tox: ToxRef<'a>,
+    handler: &'a mut dyn EventHandler,
+    /// The first panic of the iteration
+    panic: Option<CallbackPanic>,
+}
+
+/// Run `f`, catching any panic so it doesn't unwind into toxcore
+pub(crate) fn catch_panic<F: FnOnce()>(callback: &'static str, f: F) -> Result<(), CallbackPanic> {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .map_err(|payload| CallbackPanic::new(callback, payload))
+}
+
+/// Call `f` with the `Tox` and handler of the `Dispatch` in `user_data`
+unsafe fn guard<F>(user_data: *mut c_void, callback: &'static str, f: F)
+    where F: FnOnce(&mut ToxRef, &mut dyn EventHandler)
+{
+    let dispatch = &mut *(user_data as *mut Dispatch);
+    let (tox, handler) = (&mut dispatch.tox, &mut *dispatch.handler);
+    if let Err(panic) = catch_panic(callback, || f(tox, handler)) {
+        dispatch.panic.get_or_insert(panic);
     }
 }
 
-extern fn on_friend_request(_: *mut ll::Tox, public_key: *const u8, message: *const u8, length: usize, chan: *mut c_void) {
+unsafe fn str_from_raw<'a>(data: *const u8, length: usize) -> Cow<'a, str> {
+    String::from_utf8_lossy(slice::from_raw_parts(data, length))
+}
+
+extern "C" fn on_connection_status(_: *mut ll::Tox, status: Connection, user_data: *mut c_void) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        let pk: &PublicKey = &*(public_key as *const _);
-        let message = String::from_utf8_lossy(slice::from_raw_parts(message, length)).into_owned();
-        tx.send(FriendRequest(*pk, message)).unwrap();
+        guard(user_data, "self_connection_status", |tox, handler| {
+            handler.on_connection_status(tox, status);
+        });
     }
 }
 
-extern fn on_friend_message(_: *mut ll::Tox, fnum: u32, kind: MessageType,
-        message: *const u8, length: usize, chan: *mut c_void) {
+extern "C" fn on_friend_request(
+    _: *mut ll::Tox,
+    public_key: *const u8,
+    message: *const u8,
+    length: usize,
+    user_data: *mut c_void
+) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        let message = String::from_utf8_lossy(slice::from_raw_parts(message, length)).into_owned();
-        tx.send(FriendMessage(fnum, kind, message)).unwrap();
+        guard(user_data, "friend_request", |tox, handler| {
+            let pk: &PublicKey = &*(public_key as *const _);
+            handler.on_friend_request(tox, pk, &str_from_raw(message, length));
+        });
+    }
+}
 
+extern "C" fn on_friend_message(
+    _: *mut ll::Tox,
+    fnum: u32,
+    kind: MessageType,
+    message: *const u8,
+    length: usize,
+    user_data: *mut c_void
+) {
+    unsafe {
+        guard(user_data, "friend_message", |tox, handler| {
+            handler.on_friend_message(tox, FriendNumber(fnum), kind, &str_from_raw(message, length));
+        });
     }
 }
 
-extern fn on_friend_name(_: *mut ll::Tox, fnum: u32, name: *const u8, length: usize, chan: *mut c_void) {
+extern "C" fn on_friend_name(_: *mut ll::Tox, fnum: u32, name: *const u8, length: usize, user_data: *mut c_void) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        let name = String::from_utf8_lossy(slice::from_raw_parts(name, length)).into_owned();
-        tx.send(FriendName(fnum, name)).unwrap();
+        guard(user_data, "friend_name", |tox, handler| {
+            handler.on_friend_name(tox, FriendNumber(fnum), &str_from_raw(name, length));
+        });
     }
 }
 
-extern fn on_friend_status_message(_: *mut ll::Tox, fnum: u32, message: *const u8, length: usize, chan: *mut c_void) {
+extern "C" fn on_friend_status_message(
+    _: *mut ll::Tox,
+    fnum: u32,
+    message: *const u8,
+    length: usize,
+    user_data: *mut c_void
+) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        let message = String::from_utf8_lossy(slice::from_raw_parts(message, length)).into_owned();
-        tx.send(FriendStatusMessage(fnum, message)).unwrap();
+        guard(user_data, "friend_status_message", |tox, handler| {
+            handler.on_friend_status_message(tox, FriendNumber(fnum), &str_from_raw(message, length));
+        });
     }
 }
 
-extern fn on_friend_status(_: *mut ll::Tox, fnum: u32, status: UserStatus, chan: *mut c_void) {
+extern "C" fn on_friend_status(_: *mut ll::Tox, fnum: u32, status: UserStatus, user_data: *mut c_void) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        tx.send(FriendStatus(fnum, status)).unwrap();
+        guard(user_data, "friend_status", |tox, handler| {
+            handler.on_friend_status(tox, FriendNumber(fnum), status);
+        });
     }
 }
 
-extern fn on_friend_connection_status(_: *mut ll::Tox, fnum: u32, status: Connection, chan: *mut c_void) {
+extern "C" fn on_friend_connection_status(_: *mut ll::Tox, fnum: u32, status: Connection, user_data: *mut c_void) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        tx.send(FriendConnectionStatus(fnum, status)).unwrap();
+        guard(user_data, "friend_connection_status", |tox, handler| {
+            handler.on_friend_connection_status(tox, FriendNumber(fnum), status);
+        });
     }
 }
 
-extern fn on_friend_typing(_: *mut ll::Tox, fnum: u32, is_typing: bool, chan: *mut c_void) {
+extern "C" fn on_friend_typing(_: *mut ll::Tox, fnum: u32, is_typing: bool, user_data: *mut c_void) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        tx.send(FriendTyping(fnum, is_typing)).unwrap();
+        guard(user_data, "friend_typing", |tox, handler| {
+            handler.on_friend_typing(tox, FriendNumber(fnum), is_typing);
+        });
     }
 }
 
-extern fn on_friend_read_receipt(
+extern "C" fn on_friend_read_receipt(
     _: *mut ll::Tox,
     friend: u32,
     message_id: u32,
-    chan: *mut c_void
+    user_data: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        tx.send(FriendReadReceipt {
-            friend, message_id
-        }).unwrap();
+        guard(user_data, "friend_read_receipt", |tox, handler| {
+            handler.on_friend_read_receipt(tox, FriendNumber(friend), MessageId(message_id));
+        });
     }
 }
 
 // File transfer
 
-extern fn on_file_control(
+extern "C" fn on_file_control(
     _: *mut ll::Tox,
     friend: u32,
     file_number: u32,
     control: FileControl,
-    chan: *mut c_void
+    user_data: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        tx.send(FileControlReceipt {
-            friend,
-            file_number,
-            control
-        }).unwrap()
+        guard(user_data, "file_recv_control", |tox, handler| {
+            handler.on_file_control(tox, FriendNumber(friend), FileNumber(file_number), control);
+        });
     }
 }
 
-extern fn on_file_chunk_request(
+extern "C" fn on_file_chunk_request(
     _: *mut ll::Tox,
     friend: u32,
     file_number: u32,
     position: u64,
     length: usize,
-    chan: *mut c_void
+    user_data: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        tx.send(FileChunkRequest {
-            friend,
-            file_number,
-            position: position as usize,
-            length: length as usize,
-        }).unwrap()
+        guard(user_data, "file_chunk_request", |tox, handler| {
+            handler.on_file_chunk_request(tox, FriendNumber(friend), FileNumber(file_number), position as usize, length);
+        });
     }
 }
 
-extern fn on_file_receive(
+extern "C" fn on_file_receive(
     _: *mut ll::Tox,
     friend: u32,
     file_number: u32,
@@ -1574,169 +2357,139 @@ extern fn on_file_receive(
     file_size: u64,
     file_name: *const u8,
     file_name_size: usize,
-    chan: *mut c_void
+    user_data: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        let file_name =
-            String::from_utf8_lossy(
-                slice::from_raw_parts(file_name, file_name_size)
-            )
-            .into_owned();
-        tx.send(FileReceipt {
-            friend,
-            file_number,
-            kind,
-            file_size: file_size as usize,
-            file_name
-        }).unwrap()
+        guard(user_data, "file_recv", |tox, handler| {
+            handler.on_file_receive(
+                tox,
+                FriendNumber(friend),
+                FileNumber(file_number),
+                kind,
+                file_size as usize,
+                &str_from_raw(file_name, file_name_size)
+            );
+        });
     }
 }
 
-extern fn on_file_chunk_receive(
+extern "C" fn on_file_chunk_receive(
     _: *mut ll::Tox,
     friend: u32,
     file_number: u32,
     position: u64,
     data: *const u8,
     data_len: usize,
-    chan: *mut c_void
+    user_data: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        let data = Vec::from(slice::from_raw_parts(data, data_len));
-        tx.send(FileChunkReceipt {
-            friend,
-            file_number,
-            position: position as usize,
-            data
-        }).unwrap()
+        guard(user_data, "file_recv_chunk", |tox, handler| {
+            let data = slice::from_raw_parts(data, data_len);
+            handler.on_file_chunk_receive(tox, FriendNumber(friend), FileNumber(file_number), position as usize, data);
+        });
     }
 }
 
 // Conference callbacks
 
-extern fn on_conference_invite(
+extern "C" fn on_conference_invite(
     _: *mut ll::Tox,
     friend: u32,
     kind: ConferenceType,
     cookie: *const u8,
     cookie_len: usize,
-    chan: *mut c_void
+    user_data: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        let cookie = Cookie {
-            raw: slice::from_raw_parts(cookie, cookie_len).into()
-        };
-        tx.send(ConferenceInvite {
-            friend, kind, cookie,
-        }).unwrap()
+        guard(user_data, "conference_invite", |tox, handler| {
+            let cookie = slice::from_raw_parts(cookie, cookie_len);
+            handler.on_conference_invite(tox, FriendNumber(friend), kind, cookie);
+        });
     }
 }
 
-extern fn on_conference_connected(
+extern "C" fn on_conference_connected(
     _: *mut ll::Tox,
     conference: u32,
-    chan: *mut c_void
+    user_data: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        tx.send(ConferenceConnected {
-            conference
-        }).unwrap();
+        guard(user_data, "conference_connected", |tox, handler| {
+            handler.on_conference_connected(tox, ConferenceNumber(conference));
+        });
     }
 }
 
-extern fn on_conference_message(
+extern "C" fn on_conference_message(
     _: *mut ll::Tox,
     conference: u32,
     peer: u32,
     kind: MessageType,
     message: *const u8,
     len: usize,
-    chan: *mut c_void
+    user_data: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-
-        let message =
-            String::from_utf8_lossy(slice::from_raw_parts(message, len))
-            .into_owned();
-
-        tx.send(ConferenceMessage {
-            conference, peer, kind, message
-        }).unwrap();
+        guard(user_data, "conference_message", |tox, handler| {
+            handler.on_conference_message(tox, ConferenceNumber(conference), PeerNumber(peer), kind, &str_from_raw(message, len));
+        });
     }
 }
 
-extern fn on_conference_title(
+extern "C" fn on_conference_title(
     _: *mut ll::Tox,
     conference: u32,
     peer: u32,
     title: *const u8,
     len: usize,
-    chan: *mut c_void
+    user_data: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-
-        let title =
-            String::from_utf8_lossy(slice::from_raw_parts(title, len))
-            .into_owned();
-
-        tx.send(ConferenceTitle {
-            conference, peer, title
-        }).unwrap();
+        guard(user_data, "conference_title", |tox, handler| {
+            handler.on_conference_title(tox, ConferenceNumber(conference), PeerNumber(peer), &str_from_raw(title, len));
+        });
     }
 }
 
-extern fn on_conference_peer_name(
+extern "C" fn on_conference_peer_name(
     _: *mut ll::Tox,
     conference: u32,
     peer: u32,
     name: *const u8,
     len: usize,
-    chan: *mut c_void
+    user_data: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-
-        let name =
-            String::from_utf8_lossy(slice::from_raw_parts(name, len))
-            .into_owned();
-
-        tx.send(ConferencePeerName {
-            conference, peer, name
-        }).unwrap();
+        guard(user_data, "conference_peer_name", |tox, handler| {
+            handler.on_conference_peer_name(tox, ConferenceNumber(conference), PeerNumber(peer), &str_from_raw(name, len));
+        });
     }
 }
 
-extern fn on_conference_peer_list_changed(
+extern "C" fn on_conference_peer_list_changed(
     _: *mut ll::Tox,
     conference: u32,
-    chan: *mut c_void
+    user_data: *mut c_void
 ) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        tx.send(ConferencePeerListChanged {
-            conference
-        }).unwrap();
+        guard(user_data, "conference_peer_list_changed", |tox, handler| {
+            handler.on_conference_peer_list_changed(tox, ConferenceNumber(conference));
+        });
     }
 }
 
-extern fn on_lossy_package(_: *mut ll::Tox, fnum: u32, data: *const u8, length: usize, chan: *mut c_void) {
+extern "C" fn on_lossy_package(_: *mut ll::Tox, fnum: u32, data: *const u8, length: usize, user_data: *mut c_void) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        let data: Vec<u8> = From::from(slice::from_raw_parts(data, length as usize));
-        tx.send(LossyPackage(fnum, data)).unwrap();
+        guard(user_data, "friend_lossy_packet", |tox, handler| {
+            handler.on_lossy_packet(tox, FriendNumber(fnum), slice::from_raw_parts(data, length));
+        });
     }
 }
-extern fn on_lossless_package(_: *mut ll::Tox, fnum: u32, data: *const u8, length: usize, chan: *mut c_void) {
+extern "C" fn on_lossless_package(_: *mut ll::Tox, fnum: u32, data: *const u8, length: usize, user_data: *mut c_void) {
     unsafe {
-        let tx: &mut Sender<Event> = &mut *(chan as *mut _);
-        let data: Vec<u8> = From::from(slice::from_raw_parts(data, length as usize));
-        tx.send(LosslessPackage(fnum, data)).unwrap();
+        guard(user_data, "friend_lossless_packet", |tox, handler| {
+            handler.on_lossless_packet(tox, FriendNumber(fnum), slice::from_raw_parts(data, length));
+        });
     }
 }
 
@@ -0,0 +1,143 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::sync::{mpsc as async_mpsc, oneshot};
+
+use super::errors::*;
+use super::worker::{Command, Worker};
+use super::{Tox, ToxOptions, Event, Address, PublicKey, MessageType, FriendNumber, MessageId};
+
+/**
+    A `Tox` running on its own thread, for use from async code.
+
+    The thread calls `tox_iterate` on the `tox_iteration_interval` cadence and
+    runs the commands sent by `AsyncTox` handles between iterations. The
+    events go to the `Events` stream returned alongside the first handle.
+
+    `AsyncTox` is cheap to clone and can be moved into any number of tasks.
+    The thread stops once the last handle is dropped, without blocking the
+    task that drops it; the `Events` stream ends when the thread is done.
+
+    ```no_run
+    # async fn run() {
+    use rstox::core::{AsyncTox, ToxOptions, MessageType, FriendNumber};
+
+    let (tox, events) = AsyncTox::new(ToxOptions::new(), None).unwrap();
+    let replier = tox.clone();
+    tokio::spawn(async move {
+        replier.send_friend_message(FriendNumber(0), MessageType::Normal, "Hello!").await
+    });
+    // `events` is a `Stream`, use e.g. `StreamExt::next` to receive them
+    # drop(events);
+    # }
+    ```
+*/
+#[derive(Clone)]
+pub struct AsyncTox {
+    worker: Arc<Worker>,
+}
+
+/// Events of an `AsyncTox`
+pub struct Events {
+    rx: async_mpsc::UnboundedReceiver<Event>,
+}
+
+impl AsyncTox {
+    /// Start a `Tox` created with `Tox::new(opts, data)` on a new thread
+    pub fn new(opts: ToxOptions, data: Option<&[u8]>) -> Result<(AsyncTox, Events), InitError> {
+        let (events_tx, events_rx) = async_mpsc::unbounded_channel();
+        let worker = Worker::spawn(opts, data, move |event| {
+            // Nobody listens anymore, but the commands still have to be served
+            let _ = events_tx.send(event);
+        })?;
+        Ok((AsyncTox { worker: Arc::new(worker) }, Events { rx: events_rx }))
+    }
+
+    /**
+        Run `f` on the thread of the `Tox` and return its result.
+
+        Panics if the thread died because an earlier `f` panicked.
+    */
+    pub async fn call<F, R>(&self, f: F) -> R
+        where F: FnOnce(&mut Tox) -> R + Send + 'static,
+              R: Send + 'static
+    {
+        let (tx, rx) = oneshot::channel();
+        let command: Command = Box::new(move |tox: &mut Tox| {
+            // The caller may have stopped waiting for the reply
+            let _ = tx.send(f(tox));
+        });
+        if self.worker.send(command).is_err() {
+            panic!("the tox thread has panicked");
+        }
+        rx.await.expect("the tox thread has panicked")
+    }
+
+    pub async fn get_address(&self) -> Address {
+        self.call(|tox| tox.get_address()).await
+    }
+
+    pub async fn bootstrap(
+        &self, host: &str, port: u16, public_key: PublicKey
+    ) -> Result<(), BootstrapError> {
+        let host = host.to_owned();
+        self.call(move |tox| tox.bootstrap(&host, port, public_key)).await
+    }
+
+    pub async fn set_name(&self, name: &str) -> Result<(), SetInfoError> {
+        let name = name.to_owned();
+        self.call(move |tox| tox.set_name(&name)).await
+    }
+
+    pub async fn add_friend(&self, address: Address, message: &str) -> Result<(), FriendAddError> {
+        let message = message.to_owned();
+        self.call(move |tox| tox.add_friend(&address, &message)).await
+    }
+
+    pub async fn add_friend_norequest(&self, public_key: PublicKey) -> Result<(), FriendAddError> {
+        self.call(move |tox| tox.add_friend_norequest(&public_key)).await
+    }
+
+    pub async fn send_friend_message(
+        &self, fnum: FriendNumber, kind: MessageType, message: &str
+    ) -> Result<MessageId, FriendSendMessageError> {
+        let message = message.to_owned();
+        self.call(move |tox| tox.send_friend_message(fnum, kind, &message)).await
+    }
+
+    /// Save the state of the `Tox`, see `Tox::save`
+    pub async fn save(&self) -> Vec<u8> {
+        self.call(|tox| tox.save()).await
+    }
+}
+
+impl Stream for Events {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Event>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+#[test]
+fn test_async_tox_calls_and_shutdown() {
+    let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let (tox, events) = AsyncTox::new(ToxOptions::new(), None).unwrap();
+    let other = tox.clone();
+
+    rt.block_on(async {
+        let address = tox.get_address().await;
+        assert_eq!(other.call(|tox| tox.get_address()).await, address);
+        assert_eq!(tox.set_name("rstox").await, Ok(()));
+        assert_eq!(other.call(|tox| tox.get_name()).await, "rstox");
+    });
+
+    drop(tox);
+    drop(other);
+    // The stream ends once the thread has stopped
+    let mut events = events;
+    let next = std::future::poll_fn(|cx| Pin::new(&mut events).poll_next(cx));
+    assert!(rt.block_on(next).is_none());
+}
@@ -0,0 +1,109 @@
+use std::ops::{Deref, DerefMut};
+
+use super::errors::*;
+use super::{
+    Tox,
+    ConferenceNumber,
+    FriendNumber,
+    PeerNumber,
+    PublicKey,
+    ConferenceId,
+    ConferenceType,
+    MessageType,
+};
+
+/**
+    A conference of a `Tox`, see `Tox::conference` and `Tox::conferences`.
+
+    `Conference<&Tox>` only has the getters. `Tox::conference_mut` returns
+    a `Conference<&mut Tox>`, which can also send messages, invite friends,
+    change the title and leave.
+
+    The conference exists when the handle is created. If it is deleted
+    later, the getters return empty values and the actions fail with the
+    "not found" variant of their error.
+*/
+pub struct Conference<T> {
+    tox: T,
+    number: ConferenceNumber,
+    kind: ConferenceType,
+    id: ConferenceId,
+}
+
+impl<T: Deref<Target = Tox>> Conference<T> {
+    pub(crate) fn new(tox: T, number: ConferenceNumber) -> Option<Conference<T>> {
+        let kind = tox.get_conference_type(number)?;
+        let id = tox.get_conference_id(number)?;
+        Some(Conference { tox, number, kind, id })
+    }
+
+    pub fn number(&self) -> ConferenceNumber {
+        self.number
+    }
+
+    pub fn kind(&self) -> ConferenceType {
+        self.kind
+    }
+
+    pub fn id(&self) -> ConferenceId {
+        self.id
+    }
+
+    pub fn title(&self) -> String {
+        self.tox.get_conference_title(self.number).unwrap_or_default()
+    }
+
+    /// The peers currently in the conference, including ourselves
+    pub fn peers(&self) -> impl Iterator<Item = PeerNumber> {
+        let count = self.tox.conference_peer_count(self.number).unwrap_or_default();
+        (0..count).map(PeerNumber)
+    }
+
+    pub fn peer_name(&self, peer: PeerNumber) -> Result<String, ConferencePeerQueryError> {
+        self.tox.get_peer_name(self.number, peer)
+    }
+
+    pub fn peer_public_key(&self, peer: PeerNumber) -> Result<PublicKey, ConferencePeerQueryError> {
+        self.tox.get_peer_public_key(self.number, peer)
+    }
+
+}
+
+impl<T: DerefMut<Target = Tox>> Conference<T> {
+    pub fn set_title(&mut self, title: &str) -> Result<(), ConferenceTitleError> {
+        self.tox.set_conference_title(self.number, title)
+    }
+
+    pub fn send_message(
+        &mut self, kind: MessageType, message: &str
+    ) -> Result<(), ConferenceSendError> {
+        self.tox.send_conference_message(self.number, kind, message)
+    }
+
+    pub fn invite(&mut self, friend: FriendNumber) -> Result<(), ConferenceInviteError> {
+        self.tox.invite_to_conference(friend, self.number)
+    }
+
+    /// Leave the conference, see `Tox::delete_conference`. Returns `false`
+    /// if the conference was already deleted.
+    pub fn delete(mut self) -> bool {
+        self.tox.delete_conference(self.number).is_some()
+    }
+}
+
+#[test]
+fn test_conference_handles() {
+    use super::ToxOptions;
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    let number = tox.new_conference().unwrap();
+
+    assert_eq!(tox.conferences().map(|c| c.number()).collect::<Vec<_>>(), vec![number]);
+    let mut conference = tox.conference_mut(number).unwrap();
+    assert_eq!(conference.set_title("rstox"), Ok(()));
+    assert_eq!(conference.title(), "rstox");
+    assert_eq!(conference.peers().count(), 1);
+
+    assert!(conference.delete());
+    assert!(tox.conference(number).is_none());
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use super::errors::*;
+use super::{Tox, Event, Connection, FriendNumber, MessageId, MessageType};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DeliveryState {
+    /// Waiting for the read receipt
+    Pending,
+    /// The friend confirmed every part of the message
+    Delivered,
+    /// The friend went offline before confirming the message. It may or may
+    /// not have arrived.
+    Unconfirmed,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: DeliveryState,
+    /// Parts still waiting for their receipt
+    remaining: Vec<MessageId>,
+    waker: Option<Waker>,
+}
+
+impl Shared {
+    fn resolve(&mut self, state: DeliveryState) {
+        if self.state == DeliveryState::Pending {
+            self.state = state;
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/**
+    The delivery state of a message tracked by a `DeliveryTracker`.
+
+    Await it to wait until the message is delivered or unconfirmed. It can
+    be sent to and awaited on another thread.
+*/
+#[derive(Debug)]
+pub struct Delivery {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Delivery {
+    pub fn state(&self) -> DeliveryState {
+        self.shared.lock().unwrap().state
+    }
+}
+
+impl Future for Delivery {
+    type Output = DeliveryState;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<DeliveryState> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.state {
+            DeliveryState::Pending => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+            state => Poll::Ready(state),
+        }
+    }
+}
+
+/**
+    Matches outgoing friend messages with their read receipts.
+
+    Send messages through the tracker, or pass the ids of messages sent
+    otherwise to `track`, and feed it the events of the `Tox` with
+    `handle_event`. Message ids are only unique for a friend until it goes
+    offline, so all pending messages of a friend become
+    `DeliveryState::Unconfirmed` when it does.
+
+    ```no_run
+    use rstox::core::{Tox, ToxOptions, DeliveryTracker, DeliveryState, FriendNumber, MessageType};
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    let mut tracker = DeliveryTracker::new();
+    let delivery = tracker.send(&mut tox, FriendNumber(0), MessageType::Normal, "hi").unwrap();
+    while delivery.state() == DeliveryState::Pending {
+        for event in tox.iter().unwrap() {
+            tracker.handle_event(&event);
+        }
+        tox.wait();
+    }
+    ```
+*/
+#[derive(Default)]
+pub struct DeliveryTracker {
+    pending: HashMap<(FriendNumber, MessageId), Arc<Mutex<Shared>>>,
+}
+
+impl DeliveryTracker {
+    pub fn new() -> DeliveryTracker {
+        DeliveryTracker::default()
+    }
+
+    /// Track a message sent to `friend`
+    pub fn track(&mut self, friend: FriendNumber, id: MessageId) -> Delivery {
+        self.track_parts(friend, &[id])
+    }
+
+    /// Track the parts of a split message as one, see `Tox::send_friend_message_split`
+    ///
+    /// A message still tracked with one of the ids becomes
+    /// `DeliveryState::Unconfirmed` and isn't tracked anymore, its receipt
+    /// can't be told apart.
+    pub fn track_parts(&mut self, friend: FriendNumber, ids: &[MessageId]) -> Delivery {
+        let shared = Arc::new(Mutex::new(Shared {
+            state: DeliveryState::Pending,
+            remaining: ids.to_vec(),
+            waker: None,
+        }));
+        if ids.is_empty() {
+            shared.lock().unwrap().state = DeliveryState::Delivered;
+        }
+        for &id in ids {
+            if let Some(old) = self.pending.insert((friend, id), shared.clone()) {
+                self.give_up(friend, &old);
+            }
+        }
+        Delivery { shared }
+    }
+
+    /// Make `shared` unconfirmed and forget its other parts
+    fn give_up(&mut self, friend: FriendNumber, shared: &Arc<Mutex<Shared>>) {
+        let mut locked = shared.lock().unwrap();
+        locked.resolve(DeliveryState::Unconfirmed);
+        for id in locked.remaining.drain(..) {
+            let key = (friend, id);
+            if self.pending.get(&key).is_some_and(|s| Arc::ptr_eq(s, shared)) {
+                self.pending.remove(&key);
+            }
+        }
+    }
+
+    /// Send a message with `Tox::send_friend_message` and track it
+    pub fn send(
+        &mut self, tox: &mut Tox, friend: FriendNumber, kind: MessageType, message: &str
+    ) -> Result<Delivery, FriendSendMessageError> {
+        let id = tox.send_friend_message(friend, kind, message)?;
+        Ok(self.track(friend, id))
+    }
+
+    /// Send a message with `Tox::send_friend_message_split` and track its parts as one
+    ///
+    /// If a part can't be sent, the error comes with the delivery of the
+    /// parts sent before it, if any.
+    pub fn send_split(
+        &mut self, tox: &mut Tox, friend: FriendNumber, kind: MessageType, message: &str
+    ) -> Result<Delivery, DeliverySplitError> {
+        match tox.send_friend_message_split(friend, kind, message) {
+            Ok(ids) => Ok(self.track_parts(friend, &ids)),
+            Err(error) => {
+                let delivery = if error.sent.is_empty() {
+                    None
+                } else {
+                    Some(self.track_parts(friend, &error.sent))
+                };
+                Err(DeliverySplitError { delivery, error })
+            },
+        }
+    }
+
+    /// Number of messages to `friend` waiting for a receipt
+    pub fn pending(&self, friend: FriendNumber) -> usize {
+        self.pending.keys().filter(|&&(f, _)| f == friend).count()
+    }
+
+    /// Update the tracked messages from `FriendReadReceipt` and
+    /// `FriendConnectionStatus` events. Other events are ignored.
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::FriendReadReceipt { friend, message_id } =>
+                self.read_receipt(friend, message_id),
+            Event::FriendConnectionStatus(friend, Connection::None) => self.offline(friend),
+            _ => {},
+        }
+    }
+
+    /// A read receipt arrived
+    pub fn read_receipt(&mut self, friend: FriendNumber, id: MessageId) {
+        if let Some(shared) = self.pending.remove(&(friend, id)) {
+            let mut shared = shared.lock().unwrap();
+            shared.remaining.retain(|&part| part != id);
+            if shared.remaining.is_empty() {
+                shared.resolve(DeliveryState::Delivered);
+            }
+        }
+    }
+
+    /// `friend` went offline
+    pub fn offline(&mut self, friend: FriendNumber) {
+        self.pending.retain(|&(f, _), shared| {
+            if f == friend {
+                shared.lock().unwrap().resolve(DeliveryState::Unconfirmed);
+            }
+            f != friend
+        });
+    }
+}
+
+#[test]
+fn test_delivery_tracker() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::Wake;
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let friend = FriendNumber(0);
+    let mut tracker = DeliveryTracker::new();
+    let mut single = tracker.track(friend, MessageId(1));
+    let split = tracker.track_parts(friend, &[MessageId(2), MessageId(3)]);
+    let other = tracker.track(FriendNumber(1), MessageId(1));
+    assert_eq!(tracker.pending(friend), 3);
+
+    let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let waker = Waker::from(wakes.clone());
+    let mut cx = Context::from_waker(&waker);
+    assert_eq!(Pin::new(&mut single).poll(&mut cx), Poll::Pending);
+
+    tracker.handle_event(&Event::FriendReadReceipt { friend, message_id: MessageId(1) });
+    tracker.handle_event(&Event::FriendReadReceipt { friend, message_id: MessageId(2) });
+    assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+    assert_eq!(Pin::new(&mut single).poll(&mut cx), Poll::Ready(DeliveryState::Delivered));
+    assert_eq!(split.state(), DeliveryState::Pending);
+
+    tracker.handle_event(&Event::FriendConnectionStatus(friend, Connection::None));
+    assert_eq!(split.state(), DeliveryState::Unconfirmed);
+    assert_eq!(tracker.pending(friend), 0);
+    // A receipt after the friend came back with the same id changes nothing
+    tracker.handle_event(&Event::FriendReadReceipt { friend, message_id: MessageId(3) });
+    assert_eq!(split.state(), DeliveryState::Unconfirmed);
+    assert_eq!(other.state(), DeliveryState::Pending);
+
+    // Tracking a reused id gives up on the message tracked with it, and
+    // forgets its other parts
+    let split = tracker.track_parts(FriendNumber(1), &[MessageId(2), MessageId(3)]);
+    let again = tracker.track_parts(FriendNumber(1), &[MessageId(1), MessageId(2)]);
+    assert_eq!(other.state(), DeliveryState::Unconfirmed);
+    assert_eq!(split.state(), DeliveryState::Unconfirmed);
+    assert_eq!(tracker.pending(FriendNumber(1)), 2);
+    for id in 1..4 {
+        tracker.read_receipt(FriendNumber(1), MessageId(id));
+    }
+    assert_eq!(again.state(), DeliveryState::Delivered);
+    assert_eq!(tracker.pending(FriendNumber(1)), 0);
+}
+
+#[test]
+fn test_send_split_first_part_fails() {
+    use super::ToxOptions;
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    let mut tracker = DeliveryTracker::new();
+    let error = tracker.send_split(&mut tox, FriendNumber(0), MessageType::Normal, "hi")
+        .unwrap_err();
+    // Nothing was sent, so there is nothing to deliver
+    assert!(error.delivery.is_none());
+    assert_eq!(error.error, FriendSendSplitError {
+        sent: vec![],
+        error: FriendSendMessageError::NotFound,
+    });
+    assert_eq!(tracker.pending(FriendNumber(0)), 0);
+}
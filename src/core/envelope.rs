@@ -0,0 +1,111 @@
+use std::cell::Cell;
+use std::time::{Instant, SystemTime};
+
+use super::Event;
+
+/**
+    Source of the timestamps put on events.
+
+    `SystemClock` is used unless another clock is installed with
+    `ToxOptions::with_clock`, which is mostly useful in tests.
+*/
+pub trait Clock: Send {
+    /// Monotonic time
+    fn now(&self) -> Instant;
+    /// Wall-clock time
+    fn system_time(&self) -> SystemTime;
+}
+
+/// The clocks of the operating system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/**
+    An event together with the moment toxcore reported it.
+
+    The timestamps are taken inside the toxcore callback, not when the event
+    is read from the queue. Sequence numbers start at 0 and count every event
+    of one `Tox` instance, including the events of its `ToxAv`.
+*/
+#[derive(Clone, Debug)]
+pub struct Envelope {
+    pub sequence: u64,
+    pub instant: Instant,
+    pub time: SystemTime,
+    pub event: Event,
+}
+
+/// Puts events of one instance into envelopes
+pub(crate) struct Stamper {
+    clock: Box<dyn Clock>,
+    next_sequence: Cell<u64>,
+}
+
+impl Stamper {
+    pub fn new(clock: Box<dyn Clock>) -> Stamper {
+        Stamper {
+            clock,
+            next_sequence: Cell::new(0),
+        }
+    }
+
+    pub fn stamp(&self, event: Event) -> Envelope {
+        let sequence = self.next_sequence.get();
+        self.next_sequence.set(sequence.wrapping_add(1));
+        Envelope {
+            sequence,
+            instant: self.clock.now(),
+            time: self.clock.system_time(),
+            event,
+        }
+    }
+}
+
+#[test]
+fn test_stamper_uses_clock_and_counts() {
+    use std::time::Duration;
+    use std::sync::{Arc, Mutex};
+    use super::{ConferenceNumber, FriendNumber};
+
+    struct ManualClock {
+        start: Instant,
+        elapsed: Arc<Mutex<Duration>>,
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.start + *self.elapsed.lock().unwrap()
+        }
+
+        fn system_time(&self) -> SystemTime {
+            SystemTime::UNIX_EPOCH + *self.elapsed.lock().unwrap()
+        }
+    }
+
+    let start = Instant::now();
+    let elapsed = Arc::new(Mutex::new(Duration::from_secs(0)));
+    let stamper = Stamper::new(Box::new(ManualClock { start, elapsed: elapsed.clone() }));
+
+    let first = stamper.stamp(Event::FriendTyping(FriendNumber(0), true));
+    *elapsed.lock().unwrap() = Duration::from_secs(3);
+    let second = stamper.stamp(Event::ConferenceConnected { conference: ConferenceNumber(1) });
+
+    assert_eq!((first.sequence, second.sequence), (0, 1));
+    assert_eq!(first.instant, start);
+    assert_eq!(second.instant - first.instant, Duration::from_secs(3));
+    assert_eq!(second.time, SystemTime::UNIX_EPOCH + Duration::from_secs(3));
+    match second.event {
+        Event::ConferenceConnected { conference } => assert_eq!(conference, ConferenceNumber(1)),
+        event => panic!("unexpected {:?}", event),
+    }
+}
@@ -1,6 +1,29 @@
-#[repr(C)]
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+
+use super::{MessageId, Delivery, Incompatibility};
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum InitError {
+    NullError,
+    MallocError,
+    PortAllocError,
+    ProxyBadType,
+    ProxyBadHost,
+    ProxyBadPort,
+    ProxyNotFound,
+    LoadEncrypted,
+    LoadBadFormat,
+    /// The linked toxcore is not compatible with these bindings. This error is
+    /// never reported by toxcore itself.
+    Incompatible(Incompatibility),
+}
+
+/// The errors of `tox_new`, see `InitError`
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum NewError {
     // `NoError` doesn't exists in rust code
     #[doc(hidden)] NoError = 0,
     NullError = 1,
@@ -14,6 +37,23 @@ pub enum InitError {
     LoadBadFormat,
 }
 
+impl From<NewError> for InitError {
+    fn from(error: NewError) -> InitError {
+        match error {
+            NewError::NoError => unreachable!("tox_new reported no error"),
+            NewError::NullError => InitError::NullError,
+            NewError::MallocError => InitError::MallocError,
+            NewError::PortAllocError => InitError::PortAllocError,
+            NewError::ProxyBadType => InitError::ProxyBadType,
+            NewError::ProxyBadHost => InitError::ProxyBadHost,
+            NewError::ProxyBadPort => InitError::ProxyBadPort,
+            NewError::ProxyNotFound => InitError::ProxyNotFound,
+            NewError::LoadEncrypted => InitError::LoadEncrypted,
+            NewError::LoadBadFormat => InitError::LoadBadFormat,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum BootstrapError {
@@ -23,6 +63,13 @@ pub enum BootstrapError {
     BadPort,
 }
 
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GetPortError {
+    #[doc(hidden)] NoError = 0,
+    NotBound = 1,
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum SetInfoError {
@@ -57,6 +104,21 @@ pub enum FriendSendMessageError {
     Empty,
 }
 
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FriendQueryError {
+    #[doc(hidden)] NoError = 0,
+    NullError = 1,
+    FriendNotFound,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SetTypingError {
+    #[doc(hidden)] NoError = 0,
+    FriendNotFound = 1,
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum FileControlError {
@@ -176,3 +238,65 @@ pub enum ConferenceTitleError {
     InvalidLength = 2,
     FailSend = 3,
 }
+
+/**
+    A panic caught in a callback called by toxcore.
+
+    Panics must not unwind into toxcore, so they are caught and reported by
+    `Tox::tick`, `Tox::tick_with`, `Tox::iter` and `ToxAv::tick` instead.
+*/
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CallbackPanic {
+    /// The toxcore callback, e.g. `"friend_message"`
+    pub callback: &'static str,
+    /// The panic message, if the panic had one
+    pub message: Option<String>,
+}
+
+impl CallbackPanic {
+    pub(crate) fn new(callback: &'static str, payload: Box<dyn Any + Send>) -> CallbackPanic {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => Some(*message),
+            Err(payload) => payload.downcast_ref::<&str>().map(|m| m.to_string()),
+        };
+        CallbackPanic { callback, message }
+    }
+}
+
+impl fmt::Display for CallbackPanic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.message {
+            Some(ref message) => write!(f, "panic in {} callback: {}", self.callback, message),
+            None => write!(f, "panic in {} callback", self.callback),
+        }
+    }
+}
+
+impl Error for CallbackPanic {}
+
+/// Sending a part of a split friend message failed, see `Tox::send_friend_message_split`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FriendSendSplitError {
+    /// The parts sent before the failure
+    pub sent: Vec<MessageId>,
+    pub error: FriendSendMessageError,
+}
+
+/// Sending a part of a split friend message failed, see
+/// `DeliveryTracker::send_split`
+#[derive(Debug)]
+pub struct DeliverySplitError {
+    /// Tracks the parts sent before the failure, `None` if the first part
+    /// failed
+    pub delivery: Option<Delivery>,
+    pub error: FriendSendSplitError,
+}
+
+/// Sending a part of a split conference message failed, see
+/// `Tox::send_conference_message_split`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ConferenceSendSplitError {
+    /// The number of parts sent before the failure
+    pub sent: usize,
+    pub error: ConferenceSendError,
+}
@@ -0,0 +1,190 @@
+use super::{
+    Event,
+    Cookie,
+    PublicKey,
+    Connection,
+    UserStatus,
+    MessageType,
+    FileControl,
+    ConferenceType,
+    FriendNumber,
+    ConferenceNumber,
+    PeerNumber,
+    FileNumber,
+    MessageId,
+};
+
+/**
+    An `Event` that borrows its strings and data from toxcore.
+
+    It is only valid during the callback it is passed to, see
+    `Tox::tick_with` and `ToxAv::tick_with`. Nothing is copied unless the
+    handler calls `to_event`.
+*/
+#[derive(Clone, Copy, Debug)]
+pub enum EventRef<'a> {
+    ConnectionStatus(Connection),
+    FriendRequest(&'a PublicKey, &'a str),
+    FriendMessage(FriendNumber, MessageType, &'a str),
+    FriendName(FriendNumber, &'a str),
+    FriendStatusMessage(FriendNumber, &'a str),
+    FriendStatus(FriendNumber, UserStatus),
+    FriendConnectionStatus(FriendNumber, Connection),
+    FriendTyping(FriendNumber, bool),
+    FriendReadReceipt {
+        friend: FriendNumber,
+        message_id: MessageId,
+    },
+
+    FileControlReceipt {
+        friend: FriendNumber,
+        file_number: FileNumber,
+        control: FileControl,
+    },
+    FileChunkRequest {
+        friend: FriendNumber,
+        file_number: FileNumber,
+        position: usize,
+        length: usize,
+    },
+    FileReceipt {
+        friend: FriendNumber,
+        file_number: FileNumber,
+        kind: u32,
+        file_size: usize,
+        file_name: &'a str,
+    },
+    FileChunkReceipt {
+        friend: FriendNumber,
+        file_number: FileNumber,
+        position: usize,
+        data: &'a [u8],
+    },
+
+    ConferenceInvite {
+        friend: FriendNumber,
+        kind: ConferenceType,
+        cookie: &'a [u8],
+    },
+    ConferenceConnected {
+        conference: ConferenceNumber
+    },
+    ConferenceMessage {
+        conference: ConferenceNumber,
+        peer: PeerNumber,
+        kind: MessageType,
+        message: &'a str,
+    },
+    ConferenceTitle {
+        conference: ConferenceNumber,
+        peer: PeerNumber,
+        title: &'a str,
+    },
+    ConferencePeerName {
+        conference: ConferenceNumber,
+        peer: PeerNumber,
+        name: &'a str,
+    },
+    ConferencePeerListChanged {
+        conference: ConferenceNumber
+    },
+
+    LossyPackage(FriendNumber, &'a [u8]),
+    LosslessPackage(FriendNumber, &'a [u8]),
+    /// ToxAV Event
+    Call(FriendNumber, bool, bool),
+    CallState(FriendNumber, u32),
+    BitRateStatus(FriendNumber, u32, u32),
+    AudioReceiveFrame(FriendNumber, &'a [i16], usize, u8, u32),
+    VideoReceiveFrame(FriendNumber, u16, u16, &'a [u8], &'a [u8], &'a [u8], i32, i32, i32),
+}
+
+impl<'a> EventRef<'a> {
+    /// Copy the event into an owned `Event`
+    pub fn to_event(&self) -> Event {
+        match *self {
+            EventRef::ConnectionStatus(status) => Event::ConnectionStatus(status),
+            EventRef::FriendRequest(public_key, message) =>
+                Event::FriendRequest(*public_key, message.to_owned()),
+            EventRef::FriendMessage(friend, kind, message) =>
+                Event::FriendMessage(friend, kind, message.to_owned()),
+            EventRef::FriendName(friend, name) => Event::FriendName(friend, name.to_owned()),
+            EventRef::FriendStatusMessage(friend, message) =>
+                Event::FriendStatusMessage(friend, message.to_owned()),
+            EventRef::FriendStatus(friend, status) => Event::FriendStatus(friend, status),
+            EventRef::FriendConnectionStatus(friend, status) =>
+                Event::FriendConnectionStatus(friend, status),
+            EventRef::FriendTyping(friend, is_typing) => Event::FriendTyping(friend, is_typing),
+            EventRef::FriendReadReceipt { friend, message_id } =>
+                Event::FriendReadReceipt { friend, message_id },
+
+            EventRef::FileControlReceipt { friend, file_number, control } =>
+                Event::FileControlReceipt { friend, file_number, control },
+            EventRef::FileChunkRequest { friend, file_number, position, length } =>
+                Event::FileChunkRequest { friend, file_number, position, length },
+            EventRef::FileReceipt { friend, file_number, kind, file_size, file_name } =>
+                Event::FileReceipt {
+                    friend,
+                    file_number,
+                    kind,
+                    file_size,
+                    file_name: file_name.to_owned(),
+                },
+            EventRef::FileChunkReceipt { friend, file_number, position, data } =>
+                Event::FileChunkReceipt { friend, file_number, position, data: data.to_vec() },
+
+            EventRef::ConferenceInvite { friend, kind, cookie } =>
+                Event::ConferenceInvite { friend, kind, cookie: Cookie::from_bytes(cookie) },
+            EventRef::ConferenceConnected { conference } =>
+                Event::ConferenceConnected { conference },
+            EventRef::ConferenceMessage { conference, peer, kind, message } =>
+                Event::ConferenceMessage { conference, peer, kind, message: message.to_owned() },
+            EventRef::ConferenceTitle { conference, peer, title } =>
+                Event::ConferenceTitle { conference, peer, title: title.to_owned() },
+            EventRef::ConferencePeerName { conference, peer, name } =>
+                Event::ConferencePeerName { conference, peer, name: name.to_owned() },
+            EventRef::ConferencePeerListChanged { conference } =>
+                Event::ConferencePeerListChanged { conference },
+
+            EventRef::LossyPackage(friend, data) => Event::LossyPackage(friend, data.to_vec()),
+            EventRef::LosslessPackage(friend, data) =>
+                Event::LosslessPackage(friend, data.to_vec()),
+            EventRef::Call(friend, audio, video) => Event::Call(friend, audio, video),
+            EventRef::CallState(friend, state) => Event::CallState(friend, state),
+            EventRef::BitRateStatus(friend, audio, video) =>
+                Event::BitRateStatus(friend, audio, video),
+            EventRef::AudioReceiveFrame(friend, pcm, sample_count, channels, sampling_rate) =>
+                Event::AudioReceiveFrame(friend, pcm.to_vec(), sample_count, channels, sampling_rate),
+            EventRef::VideoReceiveFrame(friend, width, height, y, u, v, ystride, ustride, vstride) =>
+                Event::VideoReceiveFrame(
+                    friend, width, height, y.to_vec(), u.to_vec(), v.to_vec(), ystride, ustride, vstride
+                ),
+        }
+    }
+}
+
+#[test]
+fn test_event_ref_to_event() {
+    let data = [1u8, 2, 3];
+    let chunk = EventRef::FileChunkReceipt {
+        friend: FriendNumber(1),
+        file_number: FileNumber(2),
+        position: 3,
+        data: &data,
+    };
+    match chunk.to_event() {
+        Event::FileChunkReceipt { friend, file_number, position, data } => {
+            assert_eq!((friend, file_number, position), (FriendNumber(1), FileNumber(2), 3));
+            assert_eq!(data, vec![1, 2, 3]);
+        },
+        event => panic!("unexpected {:?}", event),
+    }
+
+    let message = EventRef::FriendMessage(FriendNumber(4), MessageType::Action, "waves");
+    match message.to_event() {
+        Event::FriendMessage(friend, MessageType::Action, message) => {
+            assert_eq!((friend, &message[..]), (FriendNumber(4), "waves"));
+        },
+        event => panic!("unexpected {:?}", event),
+    }
+}
@@ -0,0 +1,120 @@
+use std::ops::{Deref, DerefMut};
+
+use super::errors::*;
+use super::{
+    Tox,
+    FriendNumber,
+    MessageId,
+    FileNumber,
+    PublicKey,
+    UserStatus,
+    Connection,
+    MessageType,
+    FileKind,
+};
+
+/**
+    A friend of a `Tox`, see `Tox::friend` and `Tox::friends`.
+
+    `Friend<&Tox>` only has the getters. `Tox::friend_mut` returns a
+    `Friend<&mut Tox>`, which can also send to and delete the friend.
+
+    The friend exists when the handle is created. If it is deleted later, the
+    getters return empty values and the actions fail with the "not found"
+    variant of their error.
+*/
+pub struct Friend<T> {
+    tox: T,
+    number: FriendNumber,
+    public_key: PublicKey,
+}
+
+impl<T: Deref<Target = Tox>> Friend<T> {
+    pub(crate) fn new(tox: T, number: FriendNumber) -> Option<Friend<T>> {
+        let public_key = tox.get_friend_public_key(number)?;
+        Some(Friend { tox, number, public_key })
+    }
+
+    pub fn number(&self) -> FriendNumber {
+        self.number
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    pub fn name(&self) -> String {
+        self.tox.get_friend_name(self.number).unwrap_or_default()
+    }
+
+    pub fn status_message(&self) -> String {
+        self.tox.get_friend_status_message(self.number).unwrap_or_default()
+    }
+
+    pub fn status(&self) -> UserStatus {
+        self.tox.get_friend_status(self.number).unwrap_or(UserStatus::None)
+    }
+
+    pub fn connection_status(&self) -> Connection {
+        self.tox.get_friend_connection_status(self.number).unwrap_or(Connection::None)
+    }
+
+    /// Seconds since the UNIX epoch when the friend was last seen online
+    pub fn last_online(&self) -> u64 {
+        self.tox.get_friend_last_online(self.number).unwrap_or_default()
+    }
+
+    pub fn is_typing(&self) -> bool {
+        self.tox.get_friend_typing(self.number).unwrap_or_default()
+    }
+}
+
+impl<T: DerefMut<Target = Tox>> Friend<T> {
+    /// See `Tox::send_friend_message`
+    pub fn send_message(
+        &mut self, kind: MessageType, message: &str
+    ) -> Result<MessageId, FriendSendMessageError> {
+        self.tox.send_friend_message(self.number, kind, message)
+    }
+
+    /// See `Tox::send_file`
+    pub fn send_file(
+        &mut self,
+        kind: FileKind,
+        file_size: usize,
+        file_name: &str
+    ) -> Result<FileNumber, FileSendError> {
+        self.tox.send_file(self.number, kind, file_size, file_name)
+    }
+
+    /// Remove the friend from the friend list, see `Tox::delete_friend`.
+    /// Returns `false` if the friend was already deleted.
+    pub fn delete(mut self) -> bool {
+        self.tox.delete_friend(self.number).is_ok()
+    }
+}
+
+#[test]
+fn test_friend_handles() {
+    use super::ToxOptions;
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    let other = Tox::new(ToxOptions::new(), None).unwrap();
+    assert!(tox.friend(FriendNumber(0)).is_none());
+
+    tox.add_friend_norequest(&other.get_public_key()).unwrap();
+    let friends = tox.friends().collect::<Vec<_>>();
+    assert_eq!(friends.len(), 1);
+    assert_eq!(friends[0].public_key(), other.get_public_key());
+    assert_eq!(friends[0].connection_status(), Connection::None);
+
+    let number = friends[0].number();
+    let mut friend = tox.friend_mut(number).unwrap();
+    assert_eq!(
+        friend.send_message(MessageType::Normal, "hi"),
+        Err(FriendSendMessageError::NotConnected)
+    );
+    assert!(friend.delete());
+    assert!(tox.friend(number).is_none());
+    assert_eq!(tox.friends().count(), 0);
+}
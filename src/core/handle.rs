@@ -0,0 +1,139 @@
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+
+use super::errors::*;
+use super::worker::{Command, Worker};
+use super::{Tox, ToxOptions, Envelope, EventFilter};
+use super::{Address, PublicKey, MessageType, FriendNumber, MessageId};
+
+/**
+    A `Tox` running on its own thread, usable from any number of threads.
+
+    The thread iterates the `Tox` and runs the commands sent through the
+    handles between iterations. Every command blocks until the thread has run
+    it and sent back its result. Events go to the subscriptions made with
+    `subscribe`, the ones matching none of them are dropped.
+
+    The thread stops once the last clone of the handle is dropped, which
+    doesn't wait for it to finish. Don't call a `ToxHandle` from inside `call`, the thread would
+    wait for itself.
+
+    ```no_run
+    use std::thread;
+    use rstox::core::{ToxHandle, ToxOptions, Event, EventFilter, EventKind};
+
+    let tox = ToxHandle::new(ToxOptions::new(), None).unwrap();
+    let messages = tox.subscribe(EventFilter::new().kind(EventKind::FriendMessage));
+
+    let replier = tox.clone();
+    thread::spawn(move || {
+        for envelope in messages.iter() {
+            if let Event::FriendMessage(friend, kind, message) = envelope.event {
+                let _ = replier.send_friend_message(friend, kind, &message);
+            }
+        }
+    });
+    println!("{}", tox.get_address());
+    ```
+*/
+#[derive(Clone)]
+pub struct ToxHandle {
+    worker: Arc<Worker>,
+}
+
+impl ToxHandle {
+    /// Start a `Tox` created with `Tox::new(opts, data)` on a new thread
+    pub fn new(opts: ToxOptions, data: Option<&[u8]>) -> Result<ToxHandle, InitError> {
+        // The subscriptions of the `Tox` get the events, the rest is dropped
+        let worker = Worker::spawn(opts, data, |_| {})?;
+        Ok(ToxHandle { worker: Arc::new(worker) })
+    }
+
+    /// Receive the events matching `filter` from now on, see `Tox::subscribe`
+    pub fn subscribe(&self, filter: EventFilter) -> Receiver<Envelope> {
+        self.call(move |tox| tox.subscribe(filter))
+    }
+
+    /**
+        Run `f` on the thread of the `Tox` and return its result.
+
+        Panics if the thread died because an earlier `f` panicked.
+    */
+    pub fn call<F, R>(&self, f: F) -> R
+        where F: FnOnce(&mut Tox) -> R + Send + 'static,
+              R: Send + 'static
+    {
+        let (tx, rx) = channel();
+        let command: Command = Box::new(move |tox: &mut Tox| {
+            let _ = tx.send(f(tox));
+        });
+        if self.worker.send(command).is_err() {
+            panic!("the tox thread has panicked");
+        }
+        rx.recv().expect("the tox thread has panicked")
+    }
+
+    pub fn get_address(&self) -> Address {
+        self.call(|tox| tox.get_address())
+    }
+
+    pub fn bootstrap(&self, host: &str, port: u16, public_key: PublicKey) -> Result<(), BootstrapError> {
+        let host = host.to_owned();
+        self.call(move |tox| tox.bootstrap(&host, port, public_key))
+    }
+
+    pub fn set_name(&self, name: &str) -> Result<(), SetInfoError> {
+        let name = name.to_owned();
+        self.call(move |tox| tox.set_name(&name))
+    }
+
+    pub fn add_friend(&self, address: Address, message: &str) -> Result<(), FriendAddError> {
+        let message = message.to_owned();
+        self.call(move |tox| tox.add_friend(&address, &message))
+    }
+
+    pub fn add_friend_norequest(&self, public_key: PublicKey) -> Result<(), FriendAddError> {
+        self.call(move |tox| tox.add_friend_norequest(&public_key))
+    }
+
+    pub fn send_friend_message(
+        &self, fnum: FriendNumber, kind: MessageType, message: &str
+    ) -> Result<MessageId, FriendSendMessageError> {
+        let message = message.to_owned();
+        self.call(move |tox| tox.send_friend_message(fnum, kind, &message))
+    }
+
+    /// Save the state of the `Tox`, see `Tox::save`
+    pub fn save(&self) -> Vec<u8> {
+        self.call(|tox| tox.save())
+    }
+}
+
+#[test]
+fn test_tox_handle_from_threads() {
+    use std::thread;
+    use super::Nospam;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ToxHandle>();
+
+    let tox = ToxHandle::new(ToxOptions::new(), None).unwrap();
+    let events = tox.subscribe(EventFilter::new());
+    drop(tox.subscribe(EventFilter::new()));
+
+    let threads = (0..4).map(|i| {
+        let tox = tox.clone();
+        thread::spawn(move || tox.call(move |tox| {
+            tox.set_nospam(Nospam { raw: [i; 4] });
+            tox.get_nospam()
+        }))
+    }).collect::<Vec<_>>();
+    for (i, thread) in threads.into_iter().enumerate() {
+        assert_eq!(thread.join().unwrap(), Nospam { raw: [i as u8; 4] });
+    }
+
+    drop(tox);
+    // The receiver is disconnected once the thread has stopped
+    while events.try_recv().is_ok() {}
+    assert!(events.recv().is_err());
+}
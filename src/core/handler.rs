@@ -0,0 +1,436 @@
+use std::ops::Deref;
+
+use super::*;
+
+/// Forwards methods of `Tox` taking `&mut self` to the `Tox` of a `ToxRef`
+macro_rules! forward {
+    ($($name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?;)*) => {$(
+        #[doc = concat!("See `Tox::", stringify!($name), "`")]
+        #[allow(clippy::result_unit_err)]
+        pub fn $name(&mut self, $($arg: $ty),*) $(-> $ret)? {
+            self.tox.$name($($arg),*)
+        }
+    )*};
+}
+
+/**
+    The `Tox` an `EventHandler` is called with.
+
+    It derefs to the `Tox`, and has all of its methods that take `&mut self`
+    except `tick`, `tick_with`, `iter` and `iter_envelopes`. toxcore can't be
+    iterated again from its own callbacks, and the `Tox` can't be replaced
+    or dropped while it iterates, so handlers never get the `Tox` itself:
+
+    ```compile_fail
+    use rstox::core::{Tox, ToxRef, ToxOptions, EventRef};
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    tox.tick_with(&mut |tox: &mut ToxRef, _: EventRef| {
+        tox.tick().unwrap();
+    }).unwrap();
+    ```
+*/
+pub struct ToxRef<'a> {
+    pub(super) tox: &'a mut Tox,
+}
+
+impl<'a> Deref for ToxRef<'a> {
+    type Target = Tox;
+
+    fn deref(&self) -> &Tox {
+        self.tox
+    }
+}
+
+impl<'a> ToxRef<'a> {
+    forward! {
+        bootstrap(host: &str, port: u16, public_key: PublicKey) -> Result<(), BootstrapError>;
+        add_tcp_relay(host: &str, port: u16, public_key: PublicKey) -> Result<(), BootstrapError>;
+        set_nospam(nospam: Nospam);
+        set_name(name: &str) -> Result<(), SetInfoError>;
+        set_status_message(message: &str) -> Result<(), SetInfoError>;
+        set_status(status: UserStatus);
+
+        add_friend(address: &Address, message: &str) -> Result<(), FriendAddError>;
+        add_friend_norequest(address: &PublicKey) -> Result<(), FriendAddError>;
+        delete_friend(fnum: FriendNumber) -> Result<(), ()>;
+        friend_mut(fnum: FriendNumber) -> Option<Friend<&mut Tox>>;
+        set_typing(fnum: FriendNumber, is_typing: bool) -> Result<(), SetTypingError>;
+        send_friend_message(
+            fnum: FriendNumber, kind: MessageType, message: &str
+        ) -> Result<MessageId, FriendSendMessageError>;
+        send_friend_message_split(
+            fnum: FriendNumber, kind: MessageType, message: &str
+        ) -> Result<Vec<MessageId>, FriendSendSplitError>;
+
+        control_file(
+            friend: FriendNumber, file_number: FileNumber, control: FileControl
+        ) -> Result<(), FileControlError>;
+        seek_file(
+            friend: FriendNumber, file_number: FileNumber, position: usize
+        ) -> Result<(), FileSeekError>;
+        get_file_id(
+            friend: FriendNumber, file_number: FileNumber
+        ) -> Result<FileId, FileGetError>;
+        send_file(
+            friend: FriendNumber, kind: FileKind, file_size: usize, file_name: &str
+        ) -> Result<FileNumber, FileSendError>;
+        send_file_with_id(
+            friend: FriendNumber, kind: FileKind, file_size: usize, file_id: FileId, file_name: &str
+        ) -> Result<FileNumber, FileSendError>;
+        send_file_chunk(
+            friend: FriendNumber, file_number: FileNumber, position: usize, data: &[u8]
+        ) -> Result<(), FileSendChunkError>;
+        send_lossy_packet(friend: FriendNumber, data: &[u8]) -> Result<(), FriendCustomPacketError>;
+        send_lossless_packet(
+            friend: FriendNumber, data: &[u8]
+        ) -> Result<(), FriendCustomPacketError>;
+
+        new_conference() -> Result<ConferenceNumber, ()>;
+        delete_conference(conference_number: ConferenceNumber) -> Option<()>;
+        conference_mut(conference_number: ConferenceNumber) -> Option<Conference<&mut Tox>>;
+        conference_offline_peer_count(
+            conference_number: ConferenceNumber
+        ) -> Result<u32, ConferencePeerQueryError>;
+        get_offline_peer_name(
+            conference_number: ConferenceNumber, peer_number: PeerNumber
+        ) -> Result<String, ConferencePeerQueryError>;
+        get_offline_peer_public_key(
+            conference_number: ConferenceNumber, peer_number: PeerNumber
+        ) -> Result<PublicKey, ConferencePeerQueryError>;
+        get_offline_peer_last_active(
+            conference_number: ConferenceNumber, peer_number: PeerNumber
+        ) -> Result<u64, ConferencePeerQueryError>;
+        invite_to_conference(
+            friend_number: FriendNumber, conference_number: ConferenceNumber
+        ) -> Result<(), ConferenceInviteError>;
+        join_conference(
+            friend_number: FriendNumber, cookie: &Cookie
+        ) -> Result<ConferenceNumber, ConferenceJoinError>;
+        send_conference_message(
+            conference_number: ConferenceNumber, kind: MessageType, message: &str
+        ) -> Result<(), ConferenceSendError>;
+        send_conference_message_split(
+            conference_number: ConferenceNumber, kind: MessageType, message: &str
+        ) -> Result<usize, ConferenceSendSplitError>;
+        set_conference_title(
+            conference_number: ConferenceNumber, title: &str
+        ) -> Result<(), ConferenceTitleError>;
+        conference_by_id(id: &ConferenceId) -> Option<ConferenceNumber>;
+        conference_by_uid(uid: &ConferenceUid) -> Option<ConferenceNumber>;
+    }
+}
+
+/**
+    Receives the events of a `Tox` while it iterates.
+
+    Pass a handler to `Tox::tick_with`. Each method is called synchronously
+    from `tox_iterate`, with the `Tox` that produced the event, so replies can
+    be sent right away. Strings and data are borrowed from toxcore and only
+    valid for the duration of the call. Every method does nothing by default.
+
+    Handlers are given a `ToxRef` to the `Tox`, which can't iterate it. A
+    panicking handler doesn't unwind into toxcore: the panic is caught and
+    returned by `tick_with`.
+
+    Any `FnMut(&mut ToxRef, EventRef)` closure is a handler too, which gets
+    every event as an `EventRef`. `Tox::tick` queues them this way, with
+    `EventRef::to_event`.
+
+    ```no_run
+    use rstox::core::{EventHandler, Tox, ToxRef, ToxOptions, MessageType, FriendNumber};
+
+    struct Echo;
+
+    impl EventHandler for Echo {
+        fn on_friend_message(
+            &mut self, tox: &mut ToxRef, friend: FriendNumber, kind: MessageType, message: &str
+        ) {
+            let _ = tox.send_friend_message(friend, kind, message);
+        }
+    }
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    loop {
+        if let Err(panic) = tox.tick_with(&mut Echo) {
+            eprintln!("{}", panic);
+        }
+        tox.wait();
+    }
+    ```
+*/
+#[allow(unused_variables)]
+pub trait EventHandler {
+    fn on_connection_status(&mut self, tox: &mut ToxRef, status: Connection) {}
+    fn on_friend_request(&mut self, tox: &mut ToxRef, public_key: &PublicKey, message: &str) {}
+    fn on_friend_message(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber, kind: MessageType, message: &str
+    ) {}
+    fn on_friend_name(&mut self, tox: &mut ToxRef, friend: FriendNumber, name: &str) {}
+    fn on_friend_status_message(&mut self, tox: &mut ToxRef, friend: FriendNumber, message: &str) {}
+    fn on_friend_status(&mut self, tox: &mut ToxRef, friend: FriendNumber, status: UserStatus) {}
+    fn on_friend_connection_status(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber, status: Connection
+    ) {}
+    fn on_friend_typing(&mut self, tox: &mut ToxRef, friend: FriendNumber, is_typing: bool) {}
+    fn on_friend_read_receipt(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber, message_id: MessageId
+    ) {}
+
+    fn on_file_control(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber,
+        file_number: FileNumber, control: FileControl
+    ) {}
+    fn on_file_chunk_request(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber,
+        file_number: FileNumber, position: usize, length: usize
+    ) {}
+    fn on_file_receive(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber,
+        file_number: FileNumber, kind: u32, file_size: usize, file_name: &str
+    ) {}
+    fn on_file_chunk_receive(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber,
+        file_number: FileNumber, position: usize, data: &[u8]
+    ) {}
+
+    fn on_conference_invite(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber, kind: ConferenceType, cookie: &[u8]
+    ) {}
+    fn on_conference_connected(&mut self, tox: &mut ToxRef, conference: ConferenceNumber) {}
+    fn on_conference_message(
+        &mut self, tox: &mut ToxRef, conference: ConferenceNumber,
+        peer: PeerNumber, kind: MessageType, message: &str
+    ) {}
+    fn on_conference_title(
+        &mut self, tox: &mut ToxRef, conference: ConferenceNumber, peer: PeerNumber, title: &str
+    ) {}
+    fn on_conference_peer_name(
+        &mut self, tox: &mut ToxRef, conference: ConferenceNumber, peer: PeerNumber, name: &str
+    ) {}
+    fn on_conference_peer_list_changed(&mut self, tox: &mut ToxRef, conference: ConferenceNumber) {}
+
+    fn on_lossy_packet(&mut self, tox: &mut ToxRef, friend: FriendNumber, data: &[u8]) {}
+    fn on_lossless_packet(&mut self, tox: &mut ToxRef, friend: FriendNumber, data: &[u8]) {}
+}
+
+impl<F> EventHandler for F where F: FnMut(&mut ToxRef, EventRef) {
+    fn on_connection_status(&mut self, tox: &mut ToxRef, status: Connection) {
+        self(tox, EventRef::ConnectionStatus(status));
+    }
+
+    fn on_friend_request(&mut self, tox: &mut ToxRef, public_key: &PublicKey, message: &str) {
+        self(tox, EventRef::FriendRequest(public_key, message));
+    }
+
+    fn on_friend_message(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber, kind: MessageType, message: &str
+    ) {
+        self(tox, EventRef::FriendMessage(friend, kind, message));
+    }
+
+    fn on_friend_name(&mut self, tox: &mut ToxRef, friend: FriendNumber, name: &str) {
+        self(tox, EventRef::FriendName(friend, name));
+    }
+
+    fn on_friend_status_message(&mut self, tox: &mut ToxRef, friend: FriendNumber, message: &str) {
+        self(tox, EventRef::FriendStatusMessage(friend, message));
+    }
+
+    fn on_friend_status(&mut self, tox: &mut ToxRef, friend: FriendNumber, status: UserStatus) {
+        self(tox, EventRef::FriendStatus(friend, status));
+    }
+
+    fn on_friend_connection_status(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber, status: Connection
+    ) {
+        self(tox, EventRef::FriendConnectionStatus(friend, status));
+    }
+
+    fn on_friend_typing(&mut self, tox: &mut ToxRef, friend: FriendNumber, is_typing: bool) {
+        self(tox, EventRef::FriendTyping(friend, is_typing));
+    }
+
+    fn on_friend_read_receipt(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber, message_id: MessageId
+    ) {
+        self(tox, EventRef::FriendReadReceipt { friend, message_id });
+    }
+
+    fn on_file_control(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber,
+        file_number: FileNumber, control: FileControl
+    ) {
+        self(tox, EventRef::FileControlReceipt { friend, file_number, control });
+    }
+
+    fn on_file_chunk_request(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber,
+        file_number: FileNumber, position: usize, length: usize
+    ) {
+        self(tox, EventRef::FileChunkRequest { friend, file_number, position, length });
+    }
+
+    fn on_file_receive(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber,
+        file_number: FileNumber, kind: u32, file_size: usize, file_name: &str
+    ) {
+        self(tox, EventRef::FileReceipt { friend, file_number, kind, file_size, file_name });
+    }
+
+    fn on_file_chunk_receive(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber,
+        file_number: FileNumber, position: usize, data: &[u8]
+    ) {
+        self(tox, EventRef::FileChunkReceipt { friend, file_number, position, data });
+    }
+
+    fn on_conference_invite(
+        &mut self, tox: &mut ToxRef, friend: FriendNumber, kind: ConferenceType, cookie: &[u8]
+    ) {
+        self(tox, EventRef::ConferenceInvite { friend, kind, cookie });
+    }
+
+    fn on_conference_connected(&mut self, tox: &mut ToxRef, conference: ConferenceNumber) {
+        self(tox, EventRef::ConferenceConnected { conference });
+    }
+
+    fn on_conference_message(
+        &mut self, tox: &mut ToxRef, conference: ConferenceNumber,
+        peer: PeerNumber, kind: MessageType, message: &str
+    ) {
+        self(tox, EventRef::ConferenceMessage { conference, peer, kind, message });
+    }
+
+    fn on_conference_title(
+        &mut self, tox: &mut ToxRef, conference: ConferenceNumber, peer: PeerNumber, title: &str
+    ) {
+        self(tox, EventRef::ConferenceTitle { conference, peer, title });
+    }
+
+    fn on_conference_peer_name(
+        &mut self, tox: &mut ToxRef, conference: ConferenceNumber, peer: PeerNumber, name: &str
+    ) {
+        self(tox, EventRef::ConferencePeerName { conference, peer, name });
+    }
+
+    fn on_conference_peer_list_changed(&mut self, tox: &mut ToxRef, conference: ConferenceNumber) {
+        self(tox, EventRef::ConferencePeerListChanged { conference });
+    }
+
+    fn on_lossy_packet(&mut self, tox: &mut ToxRef, friend: FriendNumber, data: &[u8]) {
+        self(tox, EventRef::LossyPackage(friend, data));
+    }
+
+    fn on_lossless_packet(&mut self, tox: &mut ToxRef, friend: FriendNumber, data: &[u8]) {
+        self(tox, EventRef::LosslessPackage(friend, data));
+    }
+}
+
+#[test]
+fn test_handler_gets_tox_and_borrowed_data() {
+    use std::ptr;
+
+    #[derive(Default)]
+    struct Recorder {
+        messages: Vec<(FriendNumber, String, String)>,
+    }
+
+    impl EventHandler for Recorder {
+        fn on_friend_message(
+            &mut self, tox: &mut ToxRef, friend: FriendNumber, _: MessageType, message: &str
+        ) {
+            self.messages.push((friend, message.to_owned(), tox.get_name()));
+        }
+    }
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    tox.set_name("rstox").unwrap();
+    let mut recorder = Recorder::default();
+    let mut dispatch = Dispatch {
+        tox: ToxRef { tox: &mut tox },
+        handler: &mut recorder,
+        panic: None,
+    };
+    let message = b"hi";
+    on_friend_message(
+        ptr::null_mut(),
+        7,
+        MessageType::Normal,
+        message.as_ptr(),
+        message.len(),
+        &mut dispatch as *mut Dispatch as *mut c_void
+    );
+    // Events the handler doesn't override are ignored
+    on_friend_typing(ptr::null_mut(), 7, true, &mut dispatch as *mut Dispatch as *mut c_void);
+
+    assert_eq!(recorder.messages, vec![(FriendNumber(7), "hi".to_owned(), "rstox".to_owned())]);
+}
+
+#[test]
+fn test_handler_panic_is_caught() {
+    use std::ptr;
+
+    struct Panicking {
+        typing: Vec<FriendNumber>,
+    }
+
+    impl EventHandler for Panicking {
+        fn on_friend_message(
+            &mut self, _: &mut ToxRef, friend: FriendNumber, _: MessageType, _: &str
+        ) {
+            panic!("no messages from {}", friend);
+        }
+
+        fn on_friend_typing(&mut self, _: &mut ToxRef, friend: FriendNumber, _: bool) {
+            self.typing.push(friend);
+        }
+    }
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    let mut handler = Panicking { typing: vec![] };
+    let mut dispatch = Dispatch {
+        tox: ToxRef { tox: &mut tox },
+        handler: &mut handler,
+        panic: None,
+    };
+    let user_data = &mut dispatch as *mut Dispatch as *mut c_void;
+    let message = b"hi";
+    for friend in 1..3 {
+        on_friend_message(
+            ptr::null_mut(), friend, MessageType::Normal, message.as_ptr(), message.len(), user_data
+        );
+    }
+    // Later events still reach the handler
+    on_friend_typing(ptr::null_mut(), 3, true, user_data);
+
+    assert_eq!(dispatch.panic, Some(CallbackPanic {
+        callback: "friend_message",
+        message: Some("no messages from 1".to_owned()),
+    }));
+    assert_eq!(handler.typing, vec![FriendNumber(3)]);
+}
+
+#[test]
+#[cfg(feature = "av")]
+// toxcore isn't reentrant, so the `ToxAv` can't be ticked from a handler of its `Tox`
+fn test_nested_tick_is_rejected() {
+    use std::ptr;
+    use crate::av::ToxAv;
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    let mut av = ToxAv::new(&mut tox).unwrap();
+    let mut handler = |_: &mut ToxRef, _: EventRef| {
+        let _ = av.tick();
+    };
+    let result = tox.iterate_with(&mut handler, |user_data| {
+        on_friend_typing(ptr::null_mut(), 0, true, user_data);
+    });
+
+    assert_eq!(result, Err(CallbackPanic {
+        callback: "friend_typing",
+        message: Some("Tox iterated from inside one of its callbacks".to_owned()),
+    }));
+    // Iterating is allowed again once the iteration is over
+    assert_eq!(av.tick(), Ok(()));
+}
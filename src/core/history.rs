@@ -0,0 +1,472 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{
+    Tox,
+    Event,
+    Envelope,
+    PublicKey,
+    ConferenceId,
+    MessageType,
+    FriendNumber,
+    ConferenceNumber,
+    MessageId,
+    Delivery,
+    DeliveryState,
+    DeliveryTracker,
+};
+use super::escape::{escape, unescape};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+/// The conversation a message belongs to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Chat {
+    Friend(PublicKey),
+    Conference(ConferenceId),
+}
+
+/// A message kept in a `HistoryStore`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub chat: Chat,
+    pub direction: Direction,
+    /// The author of the message, our own key for outgoing messages
+    pub peer: PublicKey,
+    pub kind: MessageType,
+    pub time: SystemTime,
+    pub message: String,
+    /// Incoming messages are always `Delivered`
+    pub state: DeliveryState,
+}
+
+/// Identifies an entry of a `HistoryStore`. Newer entries have greater ids.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EntryId(pub u64);
+
+/**
+    Storage of message history, see `History` for recording it.
+*/
+pub trait HistoryStore {
+    /// Add a new entry
+    fn append(&mut self, entry: HistoryEntry) -> io::Result<EntryId>;
+    /// Change the delivery state of an entry
+    fn set_state(&mut self, id: EntryId, state: DeliveryState) -> io::Result<()>;
+    /**
+        Up to `limit` entries of `chat` older than `before`, or the newest
+        ones if `before` is `None`, oldest first. Pass the id of the first
+        entry as `before` to get the previous page.
+    */
+    fn page(
+        &self, chat: &Chat, before: Option<EntryId>, limit: usize
+    ) -> io::Result<Vec<(EntryId, HistoryEntry)>>;
+}
+
+/**
+    A `HistoryStore` keeping everything in one append-only file.
+
+    Entries and their state changes are appended as lines of text, and the
+    whole history is read into memory by `open`. A line cut short by a
+    crash is dropped. Read receipts can't be matched across restarts, so
+    `open` marks outgoing messages still `Pending` as `Unconfirmed`.
+*/
+pub struct FileHistory {
+    file: File,
+    entries: Vec<HistoryEntry>,
+}
+
+impl FileHistory {
+    /// Open the history stored at `path`, creating the file if there is none
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileHistory> {
+        let path = path.as_ref();
+        let mut data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        if !data.is_empty() && !data.ends_with('\n') {
+            data.truncate(data.rfind('\n').map_or(0, |i| i + 1));
+            file.set_len(data.len() as u64)?;
+        }
+
+        let mut entries: Vec<HistoryEntry> = Vec::new();
+        for line in data.lines() {
+            let invalid = || {
+                io::Error::new(io::ErrorKind::InvalidData, format!("bad history line: {}", line))
+            };
+            match line.split_once(' ') {
+                Some(("m", entry)) => entries.push(parse_entry(entry).ok_or_else(invalid)?),
+                Some(("s", change)) => {
+                    let (id, state) = change.split_once(' ').ok_or_else(invalid)?;
+                    let entry = id.parse().ok()
+                        .and_then(|id: usize| entries.get_mut(id))
+                        .ok_or_else(invalid)?;
+                    entry.state = parse_state(state).ok_or_else(invalid)?;
+                },
+                _ => return Err(invalid()),
+            }
+        }
+
+        let mut history = FileHistory { file, entries };
+        let unconfirmed = history.entries.iter()
+            .enumerate()
+            .filter(|(_, e)| {
+                e.direction == Direction::Outgoing && e.state == DeliveryState::Pending
+            })
+            .map(|(i, _)| EntryId(i as u64))
+            .collect::<Vec<_>>();
+        for id in unconfirmed {
+            history.set_state(id, DeliveryState::Unconfirmed)?;
+        }
+        Ok(history)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn write_line(&mut self, line: String) -> io::Result<()> {
+        // One write per line, so a crash can only cut the last one
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()
+    }
+}
+
+impl HistoryStore for FileHistory {
+    fn append(&mut self, entry: HistoryEntry) -> io::Result<EntryId> {
+        let chat = match entry.chat {
+            Chat::Friend(public_key) => format!("f{}", public_key),
+            Chat::Conference(id) => format!("c{}", id),
+        };
+        let direction = match entry.direction {
+            Direction::Incoming => 'i',
+            Direction::Outgoing => 'o',
+        };
+        let kind = match entry.kind {
+            MessageType::Normal => 'n',
+            MessageType::Action => 'a',
+        };
+        let time = entry.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.write_line(format!(
+            "m {} {} {} {} {}.{:09} {} {}\n",
+            chat,
+            direction,
+            entry.peer,
+            kind,
+            time.as_secs(),
+            time.subsec_nanos(),
+            state_char(entry.state),
+            escape(&entry.message),
+        ))?;
+        self.entries.push(entry);
+        Ok(EntryId(self.entries.len() as u64 - 1))
+    }
+
+    fn set_state(&mut self, id: EntryId, state: DeliveryState) -> io::Result<()> {
+        if self.entries.get(id.0 as usize).is_none_or(|e| e.state == state) {
+            return Ok(());
+        }
+        self.write_line(format!("s {} {}\n", id.0, state_char(state)))?;
+        self.entries[id.0 as usize].state = state;
+        Ok(())
+    }
+
+    fn page(
+        &self, chat: &Chat, before: Option<EntryId>, limit: usize
+    ) -> io::Result<Vec<(EntryId, HistoryEntry)>> {
+        let end = before.map_or(self.entries.len(), |id| (id.0 as usize).min(self.entries.len()));
+        let mut page = self.entries[..end].iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, e)| e.chat == *chat)
+            .take(limit)
+            .map(|(i, e)| (EntryId(i as u64), e.clone()))
+            .collect::<Vec<_>>();
+        page.reverse();
+        Ok(page)
+    }
+}
+
+fn state_char(state: DeliveryState) -> char {
+    match state {
+        DeliveryState::Pending => 'p',
+        DeliveryState::Delivered => 'd',
+        DeliveryState::Unconfirmed => 'u',
+    }
+}
+
+fn parse_state(state: &str) -> Option<DeliveryState> {
+    match state {
+        "p" => Some(DeliveryState::Pending),
+        "d" => Some(DeliveryState::Delivered),
+        "u" => Some(DeliveryState::Unconfirmed),
+        _ => None,
+    }
+}
+
+fn parse_entry(line: &str) -> Option<HistoryEntry> {
+    let mut fields = line.splitn(7, ' ');
+    let chat = fields.next()?;
+    let chat = match (chat.strip_prefix('f'), chat.strip_prefix('c')) {
+        (Some(public_key), _) => Chat::Friend(public_key.parse().ok()?),
+        (_, Some(id)) => Chat::Conference(id.parse().ok()?),
+        _ => return None,
+    };
+    let direction = match fields.next()? {
+        "i" => Direction::Incoming,
+        "o" => Direction::Outgoing,
+        _ => return None,
+    };
+    let peer = fields.next()?.parse().ok()?;
+    let kind = match fields.next()? {
+        "n" => MessageType::Normal,
+        "a" => MessageType::Action,
+        _ => return None,
+    };
+    let (secs, nanos) = fields.next()?.split_once('.')?;
+    let time = UNIX_EPOCH + Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+    let state = parse_state(fields.next()?)?;
+    let message = unescape(fields.next()?)?;
+    Some(HistoryEntry { chat, direction, peer, kind, time, message, state })
+}
+
+/**
+    Records the messages of a `Tox` into a `HistoryStore`.
+
+    Incoming messages and delivery states are taken from the envelopes passed
+    to `handle_event`, incoming messages are recorded with the time of their
+    envelope. Outgoing messages are recorded with `record_sent` and
+    `record_conference_sent` after sending them. Their delivery is tracked
+    with a `DeliveryTracker`, so messages not confirmed before the friend
+    goes offline become `DeliveryState::Unconfirmed`, as do all outgoing
+    conference messages, which have no read receipts.
+
+    ```no_run
+    use rstox::core::{Tox, ToxOptions, History, FileHistory, Chat, FriendNumber, MessageType};
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    let mut history = History::new(FileHistory::open("history.txt").unwrap());
+    let friend = FriendNumber(0);
+    let id = tox.send_friend_message(friend, MessageType::Normal, "hi").unwrap();
+    history.record_sent(&tox, friend, MessageType::Normal, "hi", &[id]).unwrap();
+    for envelope in tox.iter_envelopes().unwrap() {
+        history.handle_event(&tox, &envelope).unwrap();
+    }
+
+    let chat = Chat::Friend(tox.get_friend_public_key(friend).unwrap());
+    for (_, entry) in history.page(&chat, None, 20).unwrap() {
+        println!("{:?} {}", entry.direction, entry.message);
+    }
+    ```
+*/
+pub struct History<S> {
+    store: S,
+    tracker: DeliveryTracker,
+    /// The outgoing entries waiting for their delivery
+    pending: Vec<(EntryId, Delivery)>,
+}
+
+impl<S: HistoryStore> History<S> {
+    pub fn new(store: S) -> History<S> {
+        History {
+            store,
+            tracker: DeliveryTracker::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// See `HistoryStore::page`
+    pub fn page(
+        &self, chat: &Chat, before: Option<EntryId>, limit: usize
+    ) -> io::Result<Vec<(EntryId, HistoryEntry)>> {
+        self.store.page(chat, before, limit)
+    }
+
+    /**
+        Record incoming `FriendMessage` and `ConferenceMessage` events, and
+        update delivery states from `FriendReadReceipt` and
+        `FriendConnectionStatus` events. Other events are ignored.
+    */
+    pub fn handle_event(&mut self, tox: &Tox, envelope: &Envelope) -> io::Result<()> {
+        let event = &envelope.event;
+        let (chat, peer, kind, message) = match *event {
+            Event::FriendMessage(friend, kind, ref message) => {
+                match tox.get_friend_public_key(friend) {
+                    Some(public_key) => (Chat::Friend(public_key), public_key, kind, message),
+                    None => return Ok(()),
+                }
+            },
+            Event::ConferenceMessage { conference, peer, kind, ref message } => {
+                let chat = tox.get_conference_id(conference).map(Chat::Conference);
+                let peer = tox.get_peer_public_key(conference, peer).ok();
+                match (chat, peer) {
+                    // toxcore may echo our own messages, those are recorded
+                    // by `record_conference_sent`
+                    (Some(chat), Some(peer)) if peer != tox.get_public_key() =>
+                        (chat, peer, kind, message),
+                    _ => return Ok(()),
+                }
+            },
+            Event::FriendReadReceipt { .. } | Event::FriendConnectionStatus(..) => {
+                self.tracker.handle_event(event);
+                return self.store_delivered();
+            },
+            _ => return Ok(()),
+        };
+        self.store.append(HistoryEntry {
+            chat,
+            direction: Direction::Incoming,
+            peer,
+            kind,
+            time: envelope.time,
+            message: message.clone(),
+            state: DeliveryState::Delivered,
+        })?;
+        Ok(())
+    }
+
+    /**
+        Record a message sent to `friend` as the message ids it was sent
+        with, several of them if it was split.
+    */
+    pub fn record_sent(
+        &mut self,
+        tox: &Tox,
+        friend: FriendNumber,
+        kind: MessageType,
+        message: &str,
+        ids: &[MessageId]
+    ) -> io::Result<Option<EntryId>> {
+        let public_key = match tox.get_friend_public_key(friend) {
+            Some(public_key) => public_key,
+            None => return Ok(None),
+        };
+        let delivery = self.tracker.track_parts(friend, ids);
+        let entry = self.outgoing(tox, Chat::Friend(public_key), kind, message, delivery.state())?;
+        self.pending.push((entry, delivery));
+        // A message tracked with one of the ids before is unconfirmed now
+        self.store_delivered()?;
+        Ok(Some(entry))
+    }
+
+    /// Record a message sent to `conference`
+    pub fn record_conference_sent(
+        &mut self, tox: &Tox, conference: ConferenceNumber, kind: MessageType, message: &str
+    ) -> io::Result<Option<EntryId>> {
+        match tox.get_conference_id(conference) {
+            Some(id) => {
+                let chat = Chat::Conference(id);
+                self.outgoing(tox, chat, kind, message, DeliveryState::Unconfirmed).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn outgoing(
+        &mut self, tox: &Tox, chat: Chat, kind: MessageType, message: &str, state: DeliveryState
+    ) -> io::Result<EntryId> {
+        self.store.append(HistoryEntry {
+            chat,
+            direction: Direction::Outgoing,
+            peer: tox.get_public_key(),
+            kind,
+            time: SystemTime::now(),
+            message: message.to_owned(),
+            state,
+        })
+    }
+
+    /// Store the states of the entries whose delivery is over
+    fn store_delivered(&mut self) -> io::Result<()> {
+        let store = &mut self.store;
+        let mut result = Ok(());
+        // Entries that fail to be stored are tried again next time
+        self.pending.retain(|(entry, delivery)| match delivery.state() {
+            DeliveryState::Pending => true,
+            _ if result.is_err() => true,
+            state => {
+                result = store.set_state(*entry, state);
+                result.is_err()
+            },
+        });
+        result
+    }
+}
+
+#[test]
+fn test_file_history_pages_and_reopens() {
+    use std::env;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("rstox-history-{}.txt", process::id()));
+    let alice = PublicKey { raw: [1; 32] };
+    let me = PublicKey { raw: [2; 32] };
+    let conference = Chat::Conference(ConferenceId { raw: [3; 32] });
+    let entry = |chat, direction, peer, message: &str| HistoryEntry {
+        chat,
+        direction,
+        peer,
+        kind: MessageType::Normal,
+        time: UNIX_EPOCH + Duration::new(1_600_000_000, 5),
+        message: message.to_owned(),
+        state: DeliveryState::Pending,
+    };
+
+    let mut history = FileHistory::open(&path).unwrap();
+    assert!(history.is_empty());
+    for i in 0..5 {
+        let message = format!("line {}\nof {}", i, i);
+        history.append(entry(Chat::Friend(alice), Direction::Outgoing, me, &message)).unwrap();
+        history.append(entry(conference, Direction::Incoming, alice, "hey \\o/")).unwrap();
+    }
+    history.set_state(EntryId(2), DeliveryState::Delivered).unwrap();
+    fn ids(page: Vec<(EntryId, HistoryEntry)>) -> Vec<u64> {
+        page.into_iter().map(|(id, _)| id.0).collect()
+    }
+    // Entries of other chats are skipped
+    assert_eq!(ids(history.page(&Chat::Friend(alice), None, 2).unwrap()), vec![6, 8]);
+    let older = history.page(&Chat::Friend(alice), Some(EntryId(6)), 10).unwrap();
+    assert_eq!(ids(older.clone()), vec![0, 2, 4]);
+    assert_eq!(older[1].1.message, "line 1\nof 1");
+    assert!(history.page(&Chat::Friend(me), None, 10).unwrap().is_empty());
+
+    // A crash in the middle of a line
+    drop(history);
+    OpenOptions::new().append(true).open(&path).unwrap().write_all(b"m fABC").unwrap();
+    let mut history = FileHistory::open(&path).unwrap();
+    history.append(entry(conference, Direction::Incoming, alice, "after")).unwrap();
+    let reopened = FileHistory::open(&path).unwrap();
+    assert_eq!(reopened.len(), 11);
+    let messages = |history: &FileHistory| {
+        history.entries.iter().map(|e| e.message.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(messages(&reopened), messages(&history));
+    // Receipts for messages sent before can't arrive anymore
+    let states = reopened.entries[..4].iter().map(|e| e.state).collect::<Vec<_>>();
+    assert_eq!(states, vec![
+        DeliveryState::Unconfirmed,
+        DeliveryState::Pending,
+        DeliveryState::Delivered,
+        DeliveryState::Pending,
+    ]);
+    let reopened_again = FileHistory::open(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(reopened_again.entries, reopened.entries);
+    assert_eq!(reopened.entries[3].time, UNIX_EPOCH + Duration::new(1_600_000_000, 5));
+    assert_eq!(reopened.entries[10].message, "after");
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+
+macro_rules! number {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(pub u32);
+
+        impl From<u32> for $name {
+            fn from(raw: u32) -> $name {
+                $name(raw)
+            }
+        }
+
+        impl From<$name> for u32 {
+            fn from(number: $name) -> u32 {
+                number.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt(fmt)
+            }
+        }
+    };
+}
+
+number!(
+    /// Number of a friend. It stays the same until the friend is deleted, after
+    /// which it may be reused
+    FriendNumber
+);
+
+number!(
+    /// Number of a conference. It stays the same until the conference is
+    /// deleted, after which it may be reused
+    ConferenceNumber
+);
+
+number!(
+    /// Number of a peer within a conference. It changes when peers join or
+    /// leave
+    PeerNumber
+);
+
+number!(
+    /// Number of a file transfer, unique per friend while the transfer lasts
+    FileNumber
+);
+
+number!(
+    /// ID of a message sent to a friend, passed back in its read receipt
+    MessageId
+);
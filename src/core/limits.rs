@@ -0,0 +1,118 @@
+use super::{
+    ll,
+    MAX_NAME_LENGTH,
+    MAX_STATUSMESSAGE_LENGTH,
+    MAX_FRIENDREQUEST_LENGTH,
+    MAX_MESSAGE_LENGTH,
+    MAX_CUSTOM_PACKET_SIZE,
+    MAX_FILENAME_LENGTH,
+    MAX_HOSTNAME_LENGTH,
+};
+use super::split::split_marked;
+
+/**
+    Length limits of the linked toxcore, in bytes.
+
+    `Limits::default()` holds the limits these bindings were written against,
+    `Limits::from_library()` asks the library. Every `Tox` method that takes a
+    string checks it against the limits of the library before calling into
+    toxcore.
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Limits {
+    pub max_name_length: usize,
+    pub max_status_message_length: usize,
+    pub max_friend_request_length: usize,
+    pub max_message_length: usize,
+    pub max_custom_packet_size: usize,
+    pub max_filename_length: usize,
+    pub max_hostname_length: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_name_length: MAX_NAME_LENGTH,
+            max_status_message_length: MAX_STATUSMESSAGE_LENGTH,
+            max_friend_request_length: MAX_FRIENDREQUEST_LENGTH,
+            max_message_length: MAX_MESSAGE_LENGTH,
+            max_custom_packet_size: MAX_CUSTOM_PACKET_SIZE,
+            max_filename_length: MAX_FILENAME_LENGTH,
+            max_hostname_length: MAX_HOSTNAME_LENGTH,
+        }
+    }
+}
+
+impl Limits {
+    /// Query the limits of the linked toxcore
+    pub fn from_library() -> Limits {
+        unsafe {
+            Limits {
+                max_name_length: ll::tox_max_name_length() as usize,
+                max_status_message_length: ll::tox_max_status_message_length() as usize,
+                max_friend_request_length: ll::tox_max_friend_request_length() as usize,
+                max_message_length: ll::tox_max_message_length() as usize,
+                max_custom_packet_size: ll::tox_max_custom_packet_size() as usize,
+                max_filename_length: ll::tox_max_filename_length() as usize,
+                max_hostname_length: ll::tox_max_hostname_length() as usize,
+            }
+        }
+    }
+
+    /// Cut `name` to fit `max_name_length`
+    pub fn truncate_name<'a>(&self, name: &'a str) -> &'a str {
+        truncate_utf8(name, self.max_name_length)
+    }
+
+    /// Cut `message` to fit `max_status_message_length`
+    pub fn truncate_status_message<'a>(&self, message: &'a str) -> &'a str {
+        truncate_utf8(message, self.max_status_message_length)
+    }
+
+    /// Cut `message` to fit `max_friend_request_length`
+    pub fn truncate_friend_request<'a>(&self, message: &'a str) -> &'a str {
+        truncate_utf8(message, self.max_friend_request_length)
+    }
+
+    /// Cut `message` to fit `max_message_length`
+    pub fn truncate_message<'a>(&self, message: &'a str) -> &'a str {
+        truncate_utf8(message, self.max_message_length)
+    }
+
+    /// Split `message` into parts that fit `max_message_length`, all but the
+    /// last one ending with `PART_MARKER`, see `split_utf8`
+    pub fn split_message(&self, message: &str) -> Vec<String> {
+        split_marked(message, self.max_message_length)
+    }
+
+    /// Cut `file_name` to fit `max_filename_length`
+    pub fn truncate_filename<'a>(&self, file_name: &'a str) -> &'a str {
+        truncate_utf8(file_name, self.max_filename_length)
+    }
+}
+
+/// Return the longest prefix of `s` that is at most `max_len` bytes long and
+/// doesn't cut a character in half
+pub fn truncate_utf8(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[test]
+fn test_truncate_utf8() {
+    assert_eq!(truncate_utf8("rstox", 10), "rstox");
+    assert_eq!(truncate_utf8("rstox", 2), "rs");
+    // "ж" is 2 bytes, "€" is 3 bytes
+    assert_eq!(truncate_utf8("жж", 3), "ж");
+    assert_eq!(truncate_utf8("a€", 3), "a");
+    assert_eq!(truncate_utf8("€", 2), "");
+
+    let limits = Limits { max_name_length: 4, ..Limits::default() };
+    assert_eq!(limits.truncate_name("Сухарик"), "Су");
+}
@@ -304,7 +304,7 @@ extern "C" {
 }
 
 extern "C" {
-    pub fn tox_new(options: *const Tox_Options, error: *mut InitError) -> *mut Tox;
+    pub fn tox_new(options: *const Tox_Options, error: *mut NewError) -> *mut Tox;
     pub fn tox_kill(tox: *mut Tox);
     pub fn tox_get_savedata_size(tox: *const Tox) -> usize;
     pub fn tox_get_savedata(tox: *const Tox, savedata: *mut u8);
@@ -453,25 +453,17 @@ extern "C" {
     ) -> u64;
 }
 
-#[repr(u32)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-pub enum TOX_ERR_FRIEND_QUERY {
-    TOX_ERR_FRIEND_QUERY_OK = 0,
-    TOX_ERR_FRIEND_QUERY_NULL = 1,
-    TOX_ERR_FRIEND_QUERY_FRIEND_NOT_FOUND = 2,
-}
-
 extern "C" {
     pub fn tox_friend_get_name_size(
         tox: *const Tox,
         friend_number: u32,
-        error: *mut TOX_ERR_FRIEND_QUERY,
+        error: *mut FriendQueryError,
     ) -> usize;
     pub fn tox_friend_get_name(
         tox: *const Tox,
         friend_number: u32,
         name: *mut u8,
-        error: *mut TOX_ERR_FRIEND_QUERY,
+        error: *mut FriendQueryError,
     ) -> bool;
 }
 
@@ -490,13 +482,13 @@ extern "C" {
     pub fn tox_friend_get_status_message_size(
         tox: *const Tox,
         friend_number: u32,
-        error: *mut TOX_ERR_FRIEND_QUERY,
+        error: *mut FriendQueryError,
     ) -> usize;
     pub fn tox_friend_get_status_message(
         tox: *const Tox,
         friend_number: u32,
         status_message: *mut u8,
-        error: *mut TOX_ERR_FRIEND_QUERY,
+        error: *mut FriendQueryError,
     ) -> bool;
 }
 
@@ -518,7 +510,7 @@ extern "C" {
     pub fn tox_friend_get_status(
         tox: *const Tox,
         friend_number: u32,
-        error: *mut TOX_ERR_FRIEND_QUERY,
+        error: *mut FriendQueryError,
     ) -> UserStatus;
 }
 
@@ -536,7 +528,7 @@ extern "C" {
     pub fn tox_friend_get_connection_status(
         tox: *const Tox,
         friend_number: u32,
-        error: *mut TOX_ERR_FRIEND_QUERY,
+        error: *mut FriendQueryError,
     ) -> Connection;
 }
 
@@ -557,7 +549,7 @@ extern "C" {
     pub fn tox_friend_get_typing(
         tox: *const Tox,
         friend_number: u32,
-        error: *mut TOX_ERR_FRIEND_QUERY,
+        error: *mut FriendQueryError,
     ) -> bool;
 }
 
@@ -574,19 +566,12 @@ extern "C" {
     pub fn tox_callback_friend_typing(tox: *mut Tox, callback: tox_friend_typing_cb);
 }
 
-#[repr(u32)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-pub enum TOX_ERR_SET_TYPING {
-    TOX_ERR_SET_TYPING_OK = 0,
-    TOX_ERR_SET_TYPING_FRIEND_NOT_FOUND = 1,
-}
-
 extern "C" {
     pub fn tox_self_set_typing(
         tox: *mut Tox,
         friend_number: u32,
         typing: bool,
-        error: *mut TOX_ERR_SET_TYPING,
+        error: *mut SetTypingError,
     ) -> bool;
 }
 
@@ -1080,17 +1065,10 @@ extern "C" {
     pub fn tox_self_get_dht_id(tox: *const Tox, dht_id: *mut u8);
 }
 
-#[repr(u32)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-pub enum TOX_ERR_GET_PORT {
-    TOX_ERR_GET_PORT_OK = 0,
-    TOX_ERR_GET_PORT_NOT_BOUND = 1,
-}
-
 extern "C" {
-    pub fn tox_self_get_udp_port(tox: *const Tox, error: *mut TOX_ERR_GET_PORT) -> u16;
+    pub fn tox_self_get_udp_port(tox: *const Tox, error: *mut GetPortError) -> u16;
 }
 
 extern "C" {
-    pub fn tox_self_get_tcp_port(tox: *const Tox, error: *mut TOX_ERR_GET_PORT) -> u16;
+    pub fn tox_self_get_tcp_port(tox: *const Tox, error: *mut GetPortError) -> u16;
 }
@@ -0,0 +1,171 @@
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::c_char;
+
+use libc::c_void;
+
+use super::{ll, LogLevel, catch_panic};
+
+/// A single diagnostic message emitted by toxcore
+#[derive(Clone, Copy, Debug)]
+pub struct LogRecord<'a> {
+    pub level: LogLevel,
+    /// Source file of toxcore that emitted the message
+    pub file: &'a str,
+    pub line: u32,
+    /// Function of toxcore that emitted the message
+    pub function: &'a str,
+    pub message: &'a str,
+}
+
+impl<'a> fmt::Display for LogRecord<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}:{}({}): {}", self.file, self.line, self.function, self.message)
+    }
+}
+
+/**
+    Receives the internal logging of toxcore.
+
+    Install it with `ToxOptions::with_logger`. Records below the minimum level
+    (`LogLevel::Trace` by default, i.e. everything) are dropped before they
+    reach the sink. A panic of the sink is caught and ignored.
+*/
+pub struct ToxLogger {
+    min_level: LogLevel,
+    sink: Box<dyn FnMut(&LogRecord) + Send>,
+}
+
+impl ToxLogger {
+    /// Pass every record to `sink`
+    pub fn from_fn<F>(sink: F) -> ToxLogger
+        where F: FnMut(&LogRecord) + Send + 'static
+    {
+        ToxLogger {
+            min_level: LogLevel::Trace,
+            sink: Box::new(sink),
+        }
+    }
+
+    /// Forward records to the `log` crate with the `toxcore` target
+    #[cfg(feature = "log")]
+    pub fn log() -> ToxLogger {
+        ToxLogger::from_fn(|record| {
+            let level = match record.level {
+                LogLevel::Trace => log::Level::Trace,
+                LogLevel::Debug => log::Level::Debug,
+                LogLevel::Info => log::Level::Info,
+                LogLevel::Warning => log::Level::Warn,
+                LogLevel::Error => log::Level::Error,
+            };
+            log::logger().log(&log::Record::builder()
+                .level(level)
+                .target("toxcore")
+                .file(Some(record.file))
+                .line(Some(record.line))
+                .args(format_args!("{}: {}", record.function, record.message))
+                .build());
+        })
+    }
+
+    /// Forward records to `tracing` as events with the `toxcore` target
+    #[cfg(feature = "tracing")]
+    pub fn tracing() -> ToxLogger {
+        ToxLogger::from_fn(|record| {
+            macro_rules! event {
+                ($level:expr) => {
+                    tracing::event!(
+                        target: "toxcore",
+                        $level,
+                        file = record.file,
+                        line = record.line,
+                        function = record.function,
+                        "{}",
+                        record.message
+                    )
+                };
+            }
+            match record.level {
+                LogLevel::Trace => event!(tracing::Level::TRACE),
+                LogLevel::Debug => event!(tracing::Level::DEBUG),
+                LogLevel::Info => event!(tracing::Level::INFO),
+                LogLevel::Warning => event!(tracing::Level::WARN),
+                LogLevel::Error => event!(tracing::Level::ERROR),
+            }
+        })
+    }
+
+    /// Drop records less severe than `level`
+    pub fn min_level(mut self, level: LogLevel) -> ToxLogger {
+        self.min_level = level;
+        self
+    }
+
+    fn dispatch(&mut self, record: &LogRecord) {
+        if record.level >= self.min_level {
+            (self.sink)(record);
+        }
+    }
+}
+
+unsafe fn str_from_c<'a>(s: *const c_char) -> &'a str {
+    if s.is_null() {
+        ""
+    } else {
+        CStr::from_ptr(s).to_str().unwrap_or("<invalid utf-8>")
+    }
+}
+
+pub(crate) extern "C" fn on_log(
+    _: *mut ll::Tox,
+    level: LogLevel,
+    file: *const c_char,
+    line: u32,
+    function: *const c_char,
+    message: *const c_char,
+    logger: *mut c_void
+) {
+    // There is nobody to report a panic of the logger to, toxcore logs from
+    // all of its functions
+    let _ = catch_panic("log", || unsafe {
+        let logger: &mut ToxLogger = &mut *(logger as *mut _);
+        logger.dispatch(&LogRecord {
+            level,
+            file: str_from_c(file),
+            line,
+            function: str_from_c(function),
+            message: str_from_c(message),
+        });
+    });
+}
+
+#[test]
+fn test_logger_filters_levels() {
+    use std::ptr;
+    use std::sync::{Arc, Mutex};
+
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let sink = records.clone();
+    let mut logger = ToxLogger::from_fn(move |record| {
+        sink.lock().unwrap().push(record.to_string());
+    }).min_level(LogLevel::Info);
+    let logger_ptr = &mut logger as *mut ToxLogger as *mut c_void;
+
+    let log = |level, file: &[u8], function: &[u8], message: &[u8]| on_log(
+        ptr::null_mut(),
+        level,
+        if file.is_empty() { ptr::null() } else { file.as_ptr() as *const c_char },
+        42,
+        if function.is_empty() { ptr::null() } else { function.as_ptr() as *const c_char },
+        message.as_ptr() as *const c_char,
+        logger_ptr
+    );
+    log(LogLevel::Debug, b"network.c\0", b"bind\0", b"dropped\0");
+    log(LogLevel::Warning, b"network.c\0", b"bind\0", b"kept\0");
+    log(LogLevel::Error, b"", b"", b"no location\0");
+
+    assert_eq!(*records.lock().unwrap(), vec![
+        "network.c:42(bind): kept".to_owned(),
+        ":42(): no location".to_owned(),
+    ]);
+}
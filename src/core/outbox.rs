@@ -0,0 +1,321 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use super::errors::FriendSendMessageError;
+use super::{Tox, Event, Connection, PublicKey, MessageId, MessageType};
+use super::escape::{escape, unescape};
+
+struct Entry {
+    public_key: PublicKey,
+    kind: MessageType,
+    /// The parts not confirmed yet
+    parts: Vec<Part>,
+}
+
+struct Part {
+    text: String,
+    /// `None` if not sent yet
+    sent: Option<MessageId>,
+}
+
+/**
+    Friend messages waiting to be delivered, kept in a file.
+
+    Messages are queued per friend public key, so they survive restarts and
+    friend number changes. A message is sent as soon as its friend is online,
+    in the order messages were queued, and kept until all of its parts are
+    confirmed with a read receipt. Parts sent but not confirmed when the
+    friend goes offline are sent again when it comes back. A message toxcore
+    won't ever send is dropped.
+
+    The outbox is rewritten after every change to the queue. Long messages
+    are split with `Limits::split_message` when they are queued.
+
+    `open` doesn't send anything: messages queued before are sent when their
+    friend comes online, or by `flush`, which should be called once the
+    `Tox` is ready, as friends may be online already.
+
+    ```no_run
+    use rstox::core::{Tox, ToxOptions, Outbox, MessageType};
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    let mut outbox = Outbox::open("outbox.txt").unwrap();
+    outbox.flush(&mut tox).unwrap();
+    let friend = "951C88B7E75C867418ACDB5D273821372BB5BD652740BCDF623A4FA293E75D2F";
+    outbox.push(&mut tox, friend.parse().unwrap(), MessageType::Normal, "see you").unwrap();
+    loop {
+        for event in tox.iter().unwrap() {
+            outbox.handle_event(&mut tox, &event).unwrap();
+        }
+        tox.wait();
+    }
+    ```
+*/
+pub struct Outbox {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl Outbox {
+    /// Open the outbox stored at `path`, or an empty one if there is no file
+    pub fn open<P: Into<PathBuf>>(path: P) -> io::Result<Outbox> {
+        let path = path.into();
+        let entries = match fs::read_to_string(&path) {
+            Ok(data) => data.lines().map(parse_entry).collect::<io::Result<_>>()?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Outbox { path, entries })
+    }
+
+    /// Number of messages to `public_key` not confirmed yet
+    pub fn pending(&self, public_key: &PublicKey) -> usize {
+        self.entries.iter().filter(|e| e.public_key == *public_key).count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Queue a message, and send it right away if the friend is online
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if `message` is empty.
+    pub fn push(
+        &mut self, tox: &mut Tox, public_key: PublicKey, kind: MessageType, message: &str
+    ) -> io::Result<()> {
+        if message.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty message"));
+        }
+        let parts = tox.limits().split_message(message).into_iter()
+            .map(|text| Part { text, sent: None })
+            .collect();
+        self.entries.push(Entry { public_key, kind, parts });
+        self.save()?;
+        self.flush_friend(tox, &public_key)
+    }
+
+    /// Send the queued messages of every friend that is online
+    pub fn flush(&mut self, tox: &mut Tox) -> io::Result<()> {
+        let mut keys: Vec<PublicKey> = Vec::new();
+        for entry in &self.entries {
+            if !keys.contains(&entry.public_key) {
+                keys.push(entry.public_key);
+            }
+        }
+        for public_key in keys {
+            self.flush_friend(tox, &public_key)?;
+        }
+        Ok(())
+    }
+
+    /**
+        Update the outbox from `FriendConnectionStatus` and
+        `FriendReadReceipt` events of `tox`. Other events are ignored.
+    */
+    pub fn handle_event(&mut self, tox: &mut Tox, event: &Event) -> io::Result<()> {
+        let public_key = match *event {
+            Event::FriendConnectionStatus(friend, _) | Event::FriendReadReceipt { friend, .. } => {
+                match tox.get_friend_public_key(friend) {
+                    Some(public_key) => public_key,
+                    None => return Ok(()),
+                }
+            },
+            _ => return Ok(()),
+        };
+        match *event {
+            Event::FriendConnectionStatus(_, Connection::None) => self.offline(&public_key),
+            Event::FriendConnectionStatus(..) => self.flush_friend(tox, &public_key)?,
+            Event::FriendReadReceipt { message_id, .. } => {
+                let confirmed = self.read_receipt(&public_key, message_id);
+                if confirmed {
+                    self.save()?;
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    fn flush_friend(&mut self, tox: &mut Tox, public_key: &PublicKey) -> io::Result<()> {
+        let friend = match tox.friend_by_public_key(*public_key) {
+            Some(friend) => friend,
+            None => return Ok(()),
+        };
+        let mut failed = vec![];
+        let entries = self.entries.iter_mut()
+            .enumerate()
+            .filter(|(_, e)| e.public_key == *public_key);
+        'entries: for (index, entry) in entries {
+            for part in entry.parts.iter_mut().filter(|p| p.sent.is_none()) {
+                match tox.send_friend_message(friend, entry.kind, &part.text) {
+                    Ok(id) => part.sent = Some(id),
+                    // Offline or the send queue is full, keep the order and
+                    // retry later
+                    Err(FriendSendMessageError::NotConnected)
+                    | Err(FriendSendMessageError::SendQ) => break 'entries,
+                    // Retrying wouldn't help
+                    Err(_) => {
+                        failed.push(index);
+                        continue 'entries;
+                    },
+                }
+            }
+        }
+
+        if failed.is_empty() {
+            return Ok(());
+        }
+        for index in failed.into_iter().rev() {
+            self.entries.remove(index);
+        }
+        self.save()
+    }
+
+    /// Returns whether a part was confirmed
+    fn read_receipt(&mut self, public_key: &PublicKey, id: MessageId) -> bool {
+        let position = self.entries.iter()
+            .enumerate()
+            .filter(|(_, e)| e.public_key == *public_key)
+            .find_map(|(index, e)| {
+                e.parts.iter().position(|p| p.sent == Some(id)).map(|part| (index, part))
+            });
+        let (index, part) = match position {
+            Some(position) => position,
+            None => return false,
+        };
+        let parts = &mut self.entries[index].parts;
+        parts.remove(part);
+        if parts.is_empty() {
+            self.entries.remove(index);
+        }
+        true
+    }
+
+    fn offline(&mut self, public_key: &PublicKey) {
+        for entry in self.entries.iter_mut().filter(|e| e.public_key == *public_key) {
+            for part in &mut entry.parts {
+                part.sent = None;
+            }
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let mut data = String::new();
+        for entry in &self.entries {
+            let kind = match entry.kind {
+                MessageType::Normal => 'n',
+                MessageType::Action => 'a',
+            };
+            // Escaped parts don't contain tabs
+            let parts = entry.parts.iter().map(|p| escape(&p.text)).collect::<Vec<_>>();
+            data.push_str(&format!("{} {} {}\n", entry.public_key, kind, parts.join("\t")));
+        }
+        // Replace the file at once, so a crash doesn't leave half of it
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+fn parse_entry(line: &str) -> io::Result<Entry> {
+    let invalid = || {
+        io::Error::new(io::ErrorKind::InvalidData, format!("bad outbox entry: {}", line))
+    };
+    let mut fields = line.splitn(3, ' ');
+    let public_key = fields.next().and_then(|pk| pk.parse().ok()).ok_or_else(invalid)?;
+    let kind = match fields.next() {
+        Some("n") => MessageType::Normal,
+        Some("a") => MessageType::Action,
+        _ => return Err(invalid()),
+    };
+    let parts = fields.next()
+        .and_then(|parts| parts.split('\t').map(unescape).collect::<Option<Vec<_>>>())
+        .ok_or_else(invalid)?;
+    let parts = parts.into_iter().map(|text| Part { text, sent: None }).collect();
+    Ok(Entry { public_key, kind, parts })
+}
+
+#[test]
+fn test_outbox_keeps_messages_until_confirmed() {
+    use std::env;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("rstox-outbox-{}.txt", process::id()));
+    let alice = PublicKey { raw: [1; 32] };
+    let bob = PublicKey { raw: [2; 32] };
+
+    let part = |text: &str, sent: Option<u32>| {
+        Part { text: text.to_owned(), sent: sent.map(MessageId) }
+    };
+    let parts = |outbox: &Outbox| outbox.entries.iter()
+        .map(|e| (e.public_key, e.kind, e.parts.iter().map(|p| (p.text.clone(), p.sent)).collect()))
+        .collect::<Vec<(PublicKey, MessageType, Vec<_>)>>();
+
+    let mut outbox = Outbox::open(&path).unwrap();
+    assert!(outbox.is_empty());
+    outbox.entries.push(Entry {
+        public_key: alice,
+        kind: MessageType::Normal,
+        parts: vec![part("multi\nline\t", Some(1)), part("\\ message", Some(2))],
+    });
+    outbox.entries.push(Entry {
+        public_key: alice,
+        kind: MessageType::Action,
+        parts: vec![part("waves", Some(3))],
+    });
+    outbox.entries.push(Entry {
+        public_key: bob,
+        kind: MessageType::Normal,
+        parts: vec![part("hi", None)],
+    });
+    outbox.save().unwrap();
+
+    // Receipts are per friend, and confirm a single part
+    assert!(!outbox.read_receipt(&bob, MessageId(3)));
+    assert!(outbox.read_receipt(&alice, MessageId(3)));
+    assert!(outbox.read_receipt(&alice, MessageId(1)));
+    assert_eq!((outbox.pending(&alice), outbox.pending(&bob)), (1, 1));
+
+    // Only the unconfirmed part is sent again after going offline
+    outbox.offline(&alice);
+    assert_eq!(parts(&outbox), vec![
+        (alice, MessageType::Normal, vec![("\\ message".to_owned(), None)]),
+        (bob, MessageType::Normal, vec![("hi".to_owned(), None)]),
+    ]);
+    assert!(!outbox.read_receipt(&alice, MessageId(2)));
+
+    // The file still has all three, as nothing was saved since
+    let reopened = Outbox::open(&path).unwrap();
+    assert_eq!(parts(&reopened), vec![
+        (alice, MessageType::Normal, vec![
+            ("multi\nline\t".to_owned(), None),
+            ("\\ message".to_owned(), None),
+        ]),
+        (alice, MessageType::Action, vec![("waves".to_owned(), None)]),
+        (bob, MessageType::Normal, vec![("hi".to_owned(), None)]),
+    ]);
+    outbox.save().unwrap();
+    let reopened = Outbox::open(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(parts(&reopened), parts(&outbox));
+}
+
+#[test]
+fn test_outbox_rejects_empty_messages() {
+    use std::env;
+    use std::process;
+    use super::ToxOptions;
+
+    let path = env::temp_dir().join(format!("rstox-outbox-empty-{}.txt", process::id()));
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    let mut outbox = Outbox::open(&path).unwrap();
+    let error = outbox.push(&mut tox, PublicKey { raw: [1; 32] }, MessageType::Normal, "")
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(outbox.is_empty() && !path.exists());
+}
@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use super::{Envelope, Event};
+use super::Event::*;
+
+/**
+    What happens to events that arrive while the event queue is full.
+
+    Only `Block` keeps the queue at about `capacity` events plus those of one
+    iteration. The other policies keep iterating while the events can be
+    dropped or merged, and fall back to blocking once an event that can't be
+    is queued past `capacity`.
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OverflowPolicy {
+    /**
+        `Tox::tick` and `ToxAv::tick` don't iterate until the consumer catches
+        up, which eventually makes friends see us offline. The events of the
+        iteration that filled the queue are queued anyway; nothing is lost.
+    */
+    Block,
+    /// Drop `LossyPackage`, `AudioReceiveFrame` and `VideoReceiveFrame`,
+    /// queue the others
+    DropLossy,
+    /**
+        Merge them into queued events: only the latest status, name, typing
+        state, conference title, peer list change, bit rate and A/V frame is
+        kept per friend or conference, and a `FileChunkReceipt` continuing the
+        last queued chunk of the same file is appended to it, up to
+        `MAX_COALESCED_CHUNK` bytes. Connection changes are always kept, so
+        going offline is never missed.
+    */
+    Coalesce,
+}
+
+/// Chunks of a file are only merged up to this size by `OverflowPolicy::Coalesce`
+pub const MAX_COALESCED_CHUNK: usize = 64 * 1024;
+
+pub(crate) struct EventQueue {
+    envelopes: VecDeque<Envelope>,
+    limit: Option<(usize, OverflowPolicy)>,
+    dropped: u64,
+}
+
+impl EventQueue {
+    pub fn new(limit: Option<(usize, OverflowPolicy)>) -> EventQueue {
+        EventQueue {
+            envelopes: VecDeque::new(),
+            limit,
+            dropped: 0,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        match self.limit {
+            Some((capacity, _)) => self.envelopes.len() >= capacity,
+            None => false,
+        }
+    }
+
+    /// Whether iterating should pause until the queue is drained
+    pub fn blocks(&self) -> bool {
+        match self.limit {
+            Some((capacity, OverflowPolicy::Block)) => self.envelopes.len() >= capacity,
+            // The queue only grows past `capacity` with events that couldn't
+            // be dropped or merged
+            Some((capacity, _)) => self.envelopes.len() > capacity,
+            None => false,
+        }
+    }
+
+    /// Number of events dropped or merged into others so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn pop(&mut self) -> Option<Envelope> {
+        self.envelopes.pop_front()
+    }
+
+    pub fn push(&mut self, envelope: Envelope) {
+        if self.is_full() {
+            match self.limit.map(|(_, policy)| policy) {
+                Some(OverflowPolicy::DropLossy) if is_lossy(&envelope.event) => {
+                    self.dropped += 1;
+                    return;
+                },
+                Some(OverflowPolicy::Coalesce) => {
+                    if self.append_chunk(&envelope.event) {
+                        self.dropped += 1;
+                        return;
+                    }
+                    let key = coalesce_key(&envelope.event);
+                    let superseded = key.and_then(|key| {
+                        self.envelopes.iter().rposition(|e| coalesce_key(&e.event) == Some(key))
+                    });
+                    if let Some(index) = superseded {
+                        self.envelopes.remove(index);
+                        self.dropped += 1;
+                    }
+                },
+                _ => {},
+            }
+        }
+        self.envelopes.push_back(envelope);
+    }
+
+    /// Append a file chunk to the last queued event if it continues it
+    fn append_chunk(&mut self, event: &Event) -> bool {
+        let back = self.envelopes.back_mut().map(|e| &mut e.event);
+        match (back, event) {
+            (
+                Some(FileChunkReceipt { friend, file_number, position, data }),
+                FileChunkReceipt {
+                    friend: next_friend,
+                    file_number: next_file_number,
+                    position: next_position,
+                    data: next_data,
+                }
+            ) if friend == next_friend && file_number == next_file_number
+                // An empty chunk marks the end of the file and is kept
+                && !data.is_empty() && !next_data.is_empty()
+                && *position + data.len() == *next_position
+                && data.len() + next_data.len() <= MAX_COALESCED_CHUNK => {
+                data.extend_from_slice(next_data);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+fn is_lossy(event: &Event) -> bool {
+    matches!(event, LossyPackage(..) | AudioReceiveFrame(..) | VideoReceiveFrame(..))
+}
+
+/// Events with the same key only differ in a value of which the latest wins
+fn coalesce_key(event: &Event) -> Option<(mem::Discriminant<Event>, u32, u32)> {
+    let key = match *event {
+        FriendName(friend, _)
+        | FriendStatusMessage(friend, _)
+        | FriendStatus(friend, _)
+        | FriendTyping(friend, _)
+        | BitRateStatus(friend, ..)
+        | AudioReceiveFrame(friend, ..)
+        | VideoReceiveFrame(friend, ..) => (friend.0, 0),
+        ConferenceTitle { conference, .. }
+        | ConferencePeerListChanged { conference } => (conference.0, 0),
+        ConferencePeerName { conference, peer, .. } => (conference.0, peer.0),
+        _ => return None,
+    };
+    Some((mem::discriminant(event), key.0, key.1))
+}
+
+#[test]
+fn test_event_queue_overflow_policies() {
+    use std::time::{Instant, SystemTime};
+    use super::{FriendNumber, FileNumber, Connection};
+
+    fn envelope(sequence: u64, event: Event) -> Envelope {
+        Envelope { sequence, instant: Instant::now(), time: SystemTime::now(), event }
+    }
+    fn chunk(position: usize, data: &[u8]) -> Event {
+        FileChunkReceipt {
+            friend: FriendNumber(0),
+            file_number: FileNumber(1),
+            position,
+            data: data.to_vec(),
+        }
+    }
+    fn drain(queue: &mut EventQueue) -> Vec<u64> {
+        let mut sequences = vec![];
+        while let Some(envelope) = queue.pop() {
+            sequences.push(envelope.sequence);
+        }
+        sequences
+    }
+
+    let mut unbounded = EventQueue::new(None);
+    for i in 0..100 {
+        unbounded.push(envelope(i, LossyPackage(FriendNumber(0), vec![])));
+    }
+    assert!(!unbounded.is_full());
+
+    let mut block = EventQueue::new(Some((1, OverflowPolicy::Block)));
+    block.push(envelope(0, LossyPackage(FriendNumber(0), vec![])));
+    assert!(block.is_full() && block.blocks());
+    block.push(envelope(1, LossyPackage(FriendNumber(0), vec![])));
+    assert_eq!((drain(&mut block), block.dropped()), (vec![0, 1], 0));
+
+    let mut drop_lossy = EventQueue::new(Some((1, OverflowPolicy::DropLossy)));
+    drop_lossy.push(envelope(0, FriendTyping(FriendNumber(0), true)));
+    // Only `Block` stops the iteration
+    assert!(drop_lossy.is_full() && !drop_lossy.blocks());
+    drop_lossy.push(envelope(1, LossyPackage(FriendNumber(0), vec![])));
+    drop_lossy.push(envelope(2, FriendTyping(FriendNumber(0), false)));
+    assert_eq!((drain(&mut drop_lossy), drop_lossy.dropped()), (vec![0, 2], 1));
+
+    // Events that can't be dropped go past the capacity and stop the iteration
+    let mut chunks = EventQueue::new(Some((2, OverflowPolicy::DropLossy)));
+    for i in 0..3 {
+        chunks.push(envelope(i, chunk(i as usize * 2, b"ab")));
+        assert_eq!(chunks.blocks(), i == 2);
+    }
+    chunks.pop();
+    assert!(!chunks.blocks());
+
+    let mut coalesce = EventQueue::new(Some((2, OverflowPolicy::Coalesce)));
+    coalesce.push(envelope(0, FriendTyping(FriendNumber(0), true)));
+    coalesce.push(envelope(1, FriendTyping(FriendNumber(1), true)));
+    coalesce.push(envelope(2, FriendTyping(FriendNumber(0), false)));
+    coalesce.push(envelope(3, chunk(0, b"ab")));
+    coalesce.push(envelope(4, chunk(2, b"cd")));
+    coalesce.push(envelope(5, chunk(4, b"")));
+    assert_eq!(coalesce.dropped(), 2);
+    let events = (0..4).filter_map(|_| coalesce.pop()).map(|e| e.event).collect::<Vec<_>>();
+    match &events[..] {
+        [
+            FriendTyping(FriendNumber(1), true),
+            FriendTyping(FriendNumber(0), false),
+            FileChunkReceipt { position: 0, data, .. },
+            FileChunkReceipt { position: 4, data: end, .. },
+        ] => assert_eq!((&data[..], &end[..]), (&b"abcd"[..], &b""[..])),
+        events => panic!("unexpected {:?}", events),
+    }
+
+    // Merged chunks don't grow past `MAX_COALESCED_CHUNK`
+    let mut big = EventQueue::new(Some((1, OverflowPolicy::Coalesce)));
+    let data = vec![0; MAX_COALESCED_CHUNK / 2];
+    for i in 0..3 {
+        big.push(envelope(i, chunk(i as usize * data.len(), &data)));
+    }
+    assert!(big.blocks());
+    let sizes = (0..2).filter_map(|_| big.pop()).map(|e| match e.event {
+        FileChunkReceipt { data, .. } => data.len(),
+        event => panic!("unexpected {:?}", event),
+    }).collect::<Vec<_>>();
+    assert_eq!(sizes, vec![MAX_COALESCED_CHUNK, data.len()]);
+
+    // A friend going offline and back online is seen twice
+    let mut connection = EventQueue::new(Some((1, OverflowPolicy::Coalesce)));
+    assert!(!connection.blocks());
+    connection.push(envelope(0, FriendConnectionStatus(FriendNumber(0), Connection::None)));
+    connection.push(envelope(1, FriendConnectionStatus(FriendNumber(0), Connection::Udp)));
+    connection.push(envelope(2, ConnectionStatus(Connection::None)));
+    connection.push(envelope(3, ConnectionStatus(Connection::Tcp)));
+    assert_eq!((drain(&mut connection), connection.dropped()), (vec![0, 1, 2, 3], 0));
+}
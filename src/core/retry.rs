@@ -0,0 +1,298 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use super::errors::*;
+use super::{Tox, FriendNumber, FileNumber, MessageId, MessageType, FileControl};
+
+struct Slot<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+/**
+    The result of a send made through a `RetryQueue`, once it is final.
+
+    Await it, or check `result` after each `RetryQueue::retry`. It can be
+    sent to and awaited on another thread.
+*/
+pub struct Sending<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T: Clone> Sending<T> {
+    /// The final result, `None` while the send is queued
+    pub fn result(&self) -> Option<T> {
+        self.slot.lock().unwrap().result.clone()
+    }
+}
+
+impl<T: Clone> Future for Sending<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut slot = self.slot.lock().unwrap();
+        match slot.result {
+            Some(ref result) => Poll::Ready(result.clone()),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+/// Tries a send once, returns `false` to be tried again later
+type Job = Box<dyn FnMut(&mut Tox) -> bool>;
+
+/**
+    Retries sends that toxcore rejected because its send queue was full.
+
+    A send that fails with the `SendQ` variant of its error is queued and
+    tried again by `retry`. Nothing retries on its own: `retry` must be
+    called after every `Tox::tick` or `Tox::iter`, once toxcore had a chance
+    to empty its send queue. Sends to a friend are made in order: while some
+    are queued, new ones queue up behind them. At most `capacity` sends are
+    queued per friend: once there are that many, the queued ones are tried
+    first, and a send toxcore still can't take fails with `SendQ`. All other
+    errors are final.
+
+    ```no_run
+    use rstox::core::{Tox, ToxOptions, RetryQueue, FriendNumber, MessageType};
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    let mut retry = RetryQueue::new(64);
+    let sending = retry.send_friend_message(&mut tox, FriendNumber(0), MessageType::Normal, "hi");
+    while sending.result().is_none() {
+        tox.tick().unwrap();
+        retry.retry(&mut tox);
+        tox.wait();
+    }
+    ```
+*/
+pub struct RetryQueue {
+    capacity: usize,
+    queues: HashMap<FriendNumber, VecDeque<Job>>,
+}
+
+impl RetryQueue {
+    pub fn new(capacity: usize) -> RetryQueue {
+        RetryQueue {
+            capacity,
+            queues: HashMap::new(),
+        }
+    }
+
+    /// Number of sends queued for `friend`
+    pub fn depth(&self, friend: FriendNumber) -> usize {
+        self.queues.get(&friend).map_or(0, VecDeque::len)
+    }
+
+    /// Number of sends queued for all friends
+    pub fn len(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    /// Try the queued sends again, in order, until toxcore rejects one
+    pub fn retry(&mut self, tox: &mut Tox) {
+        for queue in self.queues.values_mut() {
+            run(queue, tox);
+        }
+        self.queues.retain(|_, queue| !queue.is_empty());
+    }
+
+    /// Like `retry`, for the sends to `friend` only
+    fn retry_friend(&mut self, tox: &mut Tox, friend: FriendNumber) {
+        if let Some(queue) = self.queues.get_mut(&friend) {
+            run(queue, tox);
+            if queue.is_empty() {
+                self.queues.remove(&friend);
+            }
+        }
+    }
+
+    /// See `Tox::send_friend_message`
+    pub fn send_friend_message(
+        &mut self, tox: &mut Tox, friend: FriendNumber, kind: MessageType, message: &str
+    ) -> Sending<Result<MessageId, FriendSendMessageError>> {
+        let message = message.to_owned();
+        self.push(tox, friend, FriendSendMessageError::SendQ, move |tox| {
+            tox.send_friend_message(friend, kind, &message)
+        })
+    }
+
+    /// See `Tox::send_file_chunk`
+    pub fn send_file_chunk(
+        &mut self,
+        tox: &mut Tox,
+        friend: FriendNumber,
+        file_number: FileNumber,
+        position: usize,
+        data: &[u8]
+    ) -> Sending<Result<(), FileSendChunkError>> {
+        let data = data.to_vec();
+        self.push(tox, friend, FileSendChunkError::SendQ, move |tox| {
+            tox.send_file_chunk(friend, file_number, position, &data)
+        })
+    }
+
+    /// See `Tox::control_file`
+    pub fn control_file(
+        &mut self,
+        tox: &mut Tox,
+        friend: FriendNumber,
+        file_number: FileNumber,
+        control: FileControl
+    ) -> Sending<Result<(), FileControlError>> {
+        self.push(tox, friend, FileControlError::SendQ, move |tox| {
+            tox.control_file(friend, file_number, control)
+        })
+    }
+
+    /// See `Tox::send_lossy_packet`
+    pub fn send_lossy_packet(
+        &mut self, tox: &mut Tox, friend: FriendNumber, data: &[u8]
+    ) -> Sending<Result<(), FriendCustomPacketError>> {
+        let data = data.to_vec();
+        self.push(tox, friend, FriendCustomPacketError::SendQ, move |tox| {
+            tox.send_lossy_packet(friend, &data)
+        })
+    }
+
+    /// See `Tox::send_lossless_packet`
+    pub fn send_lossless_packet(
+        &mut self, tox: &mut Tox, friend: FriendNumber, data: &[u8]
+    ) -> Sending<Result<(), FriendCustomPacketError>> {
+        let data = data.to_vec();
+        self.push(tox, friend, FriendCustomPacketError::SendQ, move |tox| {
+            tox.send_lossless_packet(friend, &data)
+        })
+    }
+
+    fn push<R, E, F>(
+        &mut self, tox: &mut Tox, friend: FriendNumber, sendq: E, mut attempt: F
+    ) -> Sending<Result<R, E>>
+        where F: FnMut(&mut Tox) -> Result<R, E> + 'static,
+              R: 'static,
+              E: PartialEq + Clone + 'static
+    {
+        let slot = Arc::new(Mutex::new(Slot { result: None, waker: None }));
+        let sending = Sending { slot: slot.clone() };
+        let rejected = sendq.clone();
+        let job_slot = slot.clone();
+        let mut job: Job = Box::new(move |tox| {
+            let result = attempt(tox);
+            if matches!(result, Err(ref e) if *e == sendq) {
+                return false;
+            }
+            let mut slot = job_slot.lock().unwrap();
+            slot.result = Some(result);
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+            true
+        });
+
+        if self.depth(friend) >= self.capacity {
+            self.retry_friend(tox, friend);
+        }
+        if self.depth(friend) == 0 && job(tox) {
+            return sending;
+        }
+        if self.depth(friend) >= self.capacity {
+            slot.lock().unwrap().result = Some(Err(rejected));
+        } else {
+            self.queues.entry(friend).or_default().push_back(job);
+        }
+        sending
+    }
+}
+
+/// Run the jobs of `queue` in order until one has to be tried again later
+fn run(queue: &mut VecDeque<Job>, tox: &mut Tox) {
+    while let Some(job) = queue.front_mut() {
+        if !job(tox) {
+            break;
+        }
+        queue.pop_front();
+    }
+}
+
+#[test]
+fn test_retry_queue_keeps_order() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::ToxOptions;
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    let mut retry = RetryQueue::new(2);
+    let friend = FriendNumber(0);
+
+    // toxcore accepts one send per iteration
+    let budget = Rc::new(RefCell::new(0));
+    let sent = Rc::new(RefCell::new(vec![]));
+    let send = |retry: &mut RetryQueue, tox: &mut Tox, n| {
+        let (budget, sent) = (budget.clone(), sent.clone());
+        retry.push(tox, friend, FriendSendMessageError::SendQ, move |_| {
+            if *budget.borrow() == 0 {
+                return Err(FriendSendMessageError::SendQ);
+            }
+            *budget.borrow_mut() -= 1;
+            sent.borrow_mut().push(n);
+            Ok(MessageId(n))
+        })
+    };
+
+    let first = send(&mut retry, &mut tox, 1);
+    let second = send(&mut retry, &mut tox, 2);
+    let third = send(&mut retry, &mut tox, 3);
+    assert_eq!(retry.depth(friend), 2);
+    assert_eq!(third.result(), Some(Err(FriendSendMessageError::SendQ)));
+
+    *budget.borrow_mut() = 1;
+    retry.retry(&mut tox);
+    assert_eq!((first.result(), second.result()), (Some(Ok(MessageId(1))), None));
+    // Queued behind the second one, even though toxcore would take it
+    *budget.borrow_mut() = 1;
+    let fourth = send(&mut retry, &mut tox, 4);
+    retry.retry(&mut tox);
+    assert_eq!(second.result(), Some(Ok(MessageId(2))));
+    assert_eq!(fourth.result(), None);
+
+    *budget.borrow_mut() = 1;
+    retry.retry(&mut tox);
+    assert_eq!(fourth.result(), Some(Ok(MessageId(4))));
+    assert!(retry.is_empty());
+    assert_eq!(*sent.borrow(), vec![1, 2, 4]);
+
+    // Other errors are final
+    let unknown = retry.send_friend_message(&mut tox, FriendNumber(7), MessageType::Normal, "hi");
+    assert_eq!(unknown.result(), Some(Err(FriendSendMessageError::NotFound)));
+
+    // Without capacity sends are still tried, but nothing is queued
+    let mut unqueued = RetryQueue::new(0);
+    let unknown = unqueued.send_friend_message(&mut tox, friend, MessageType::Normal, "hi");
+    assert_eq!(unknown.result(), Some(Err(FriendSendMessageError::NotFound)));
+    *budget.borrow_mut() = 1;
+    let accepted = send(&mut unqueued, &mut tox, 5);
+    let rejected = send(&mut unqueued, &mut tox, 6);
+    assert_eq!(accepted.result(), Some(Ok(MessageId(5))));
+    assert_eq!(rejected.result(), Some(Err(FriendSendMessageError::SendQ)));
+    assert!(unqueued.is_empty() && unqueued.len() == 0);
+
+    // A full queue is retried before giving up on a new send
+    *budget.borrow_mut() = 0;
+    let first = send(&mut retry, &mut tox, 7);
+    let second = send(&mut retry, &mut tox, 8);
+    *budget.borrow_mut() = 2;
+    let third = send(&mut retry, &mut tox, 9);
+    assert_eq!((first.result(), second.result()), (Some(Ok(MessageId(7))), Some(Ok(MessageId(8)))));
+    assert_eq!(third.result(), None);
+    assert_eq!(retry.depth(friend), 1);
+}
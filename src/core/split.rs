@@ -0,0 +1,241 @@
+use std::time::{Duration, Instant};
+
+use super::{Envelope, Event, FriendNumber, ConferenceNumber, PeerNumber, MessageType};
+use super::limits::truncate_utf8;
+
+/// Ends every part but the last of a message sent with
+/// `Tox::send_friend_message_split`, so `MessageJoiner` can tell the parts
+/// from messages that weren't split. It is a word joiner, clients don't show it.
+pub const PART_MARKER: &str = "\u{2060}";
+
+/// Every part but the last one is at least this long
+fn min_part_len(max_len: usize) -> usize {
+    (max_len - max_len / 4).saturating_sub(3)
+}
+
+/**
+    Split `s` into parts of at most `max_len` bytes.
+
+    A part ends after the last newline in its final quarter, or else after the
+    last whitespace there, or else at the last character boundary. The
+    separators are kept, so the parts concatenate to `s` again.
+*/
+pub fn split_utf8(s: &str, max_len: usize) -> Vec<&str> {
+    let min_len = min_part_len(max_len);
+    let mut parts = vec![];
+    let mut rest = s;
+    while rest.len() > max_len {
+        let head = truncate_utf8(rest, max_len);
+        let split_after = |separator: fn(&char) -> bool| {
+            head.char_indices()
+                .rev()
+                .map(|(i, c)| (i + c.len_utf8(), c))
+                .take_while(|&(end, _)| end >= min_len)
+                .find(|(_, c)| separator(c))
+                .map(|(end, _)| end)
+        };
+        let mut end = split_after(|&c| c == '\n')
+            .or_else(|| split_after(|c| c.is_whitespace()))
+            .unwrap_or(head.len());
+        if end == 0 {
+            // `max_len` is shorter than the first character
+            end = rest.chars().next().map_or(0, char::len_utf8);
+        }
+        parts.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() || parts.is_empty() {
+        parts.push(rest);
+    }
+    parts
+}
+
+/// Split `s` like `split_utf8`, but end every part but the last one with
+/// `PART_MARKER`, which counts towards `max_len`
+pub(crate) fn split_marked(s: &str, max_len: usize) -> Vec<String> {
+    if s.len() <= max_len {
+        return vec![s.to_owned()];
+    }
+    let mut parts = split_utf8(s, max_len.saturating_sub(PART_MARKER.len())).into_iter()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    let last = parts.len() - 1;
+    for part in &mut parts[..last] {
+        part.push_str(PART_MARKER);
+    }
+    parts
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Sender {
+    Friend(FriendNumber),
+    Peer(ConferenceNumber, PeerNumber),
+}
+
+fn message_of(event: &mut Event) -> Option<(Sender, MessageType, &mut String)> {
+    match *event {
+        Event::FriendMessage(friend, kind, ref mut message) =>
+            Some((Sender::Friend(friend), kind, message)),
+        Event::ConferenceMessage { conference, peer, kind, ref mut message } =>
+            Some((Sender::Peer(conference, peer), kind, message)),
+        _ => None,
+    }
+}
+
+struct Pending {
+    envelope: Envelope,
+    sender: Sender,
+    kind: MessageType,
+    last_part: Instant,
+}
+
+/**
+    Joins incoming messages that were split by `Tox::send_friend_message_split`
+    or `Tox::send_conference_message_split` back together.
+
+    A friend or conference message ending with `PART_MARKER` is held back
+    until the next message of the same sender arrives within `window`, which
+    is then appended to it without the marker. The joined message keeps the
+    `Envelope` of its first part. Other events pass through unchanged.
+
+    ```no_run
+    use std::time::{Duration, Instant};
+    use rstox::core::{Tox, ToxOptions, MessageJoiner};
+
+    let mut tox = Tox::new(ToxOptions::new(), None).unwrap();
+    let mut joiner = MessageJoiner::new(Duration::from_secs(1));
+    loop {
+        let mut envelopes = Vec::new();
+        for envelope in tox.iter_envelopes().unwrap() {
+            envelopes.extend(joiner.push(envelope));
+        }
+        envelopes.extend(joiner.flush(Instant::now()));
+        for envelope in envelopes {
+            println!("{:?}", envelope.event);
+        }
+        tox.wait();
+    }
+    ```
+*/
+pub struct MessageJoiner {
+    window: Duration,
+    pending: Vec<Pending>,
+}
+
+impl MessageJoiner {
+    pub fn new(window: Duration) -> MessageJoiner {
+        MessageJoiner {
+            window,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Feed the next envelope, returns those ready to be processed
+    pub fn push(&mut self, mut envelope: Envelope) -> Vec<Envelope> {
+        let mut ready = self.flush(envelope.instant);
+        let (sender, kind, message) = match message_of(&mut envelope.event) {
+            Some(message) => message,
+            None => {
+                ready.push(envelope);
+                return ready;
+            },
+        };
+        let is_part = message.ends_with(PART_MARKER);
+        if is_part {
+            message.truncate(message.len() - PART_MARKER.len());
+        }
+
+        if let Some(i) = self.pending.iter().position(|p| p.sender == sender) {
+            let mut pending = self.pending.remove(i);
+            if pending.kind == kind {
+                if let Some((_, _, joined)) = message_of(&mut pending.envelope.event) {
+                    joined.push_str(message);
+                }
+                if is_part {
+                    pending.last_part = envelope.instant;
+                    self.pending.push(pending);
+                } else {
+                    ready.push(pending.envelope);
+                }
+                return ready;
+            }
+            ready.push(pending.envelope);
+        }
+
+        if is_part {
+            let last_part = envelope.instant;
+            self.pending.push(Pending { envelope, sender, kind, last_part });
+        } else {
+            ready.push(envelope);
+        }
+        ready
+    }
+
+    /// Release the messages whose next part didn't arrive within the window
+    pub fn flush(&mut self, now: Instant) -> Vec<Envelope> {
+        let window = self.window;
+        let (expired, pending) = self.pending.drain(..)
+            .partition(|p| now.saturating_duration_since(p.last_part) > window);
+        self.pending = pending;
+        expired.into_iter().map(|p: Pending| p.envelope).collect()
+    }
+}
+
+#[test]
+fn test_split_and_join() {
+    use std::time::SystemTime;
+
+    assert_eq!(split_utf8("short", 10), vec!["short"]);
+    assert_eq!(split_utf8("", 10), vec![""]);
+    // Newlines win over spaces, spaces over cutting words
+    assert_eq!(split_utf8("aaaaaa\nbb cc dd", 12), vec!["aaaaaa\n", "bb cc dd"]);
+    assert_eq!(split_utf8("aaaaaaaaa bbbbbbbbbbbbbb", 12), vec!["aaaaaaaaa ", "bbbbbbbbbbbb", "bb"]);
+    // Separators too early in the part are ignored
+    assert_eq!(split_utf8("a bbbbbbbbbbbbb", 12), vec!["a bbbbbbbbbb", "bbb"]);
+    // Characters are never cut in half, "ж" is 2 bytes
+    assert_eq!(split_utf8("жжжжжжж", 5), vec!["жж", "жж", "жж", "ж"]);
+    assert_eq!(split_utf8("ж", 1), vec!["ж"]);
+
+    let text = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(9);
+    assert_eq!(split_utf8(&text, 100).concat(), text);
+    assert_eq!(split_marked("short", 10), vec!["short"]);
+    let parts = split_marked(&text, 100);
+    assert!(parts.len() > 5 && parts.iter().all(|part| part.len() <= 100));
+    assert!(parts[..parts.len() - 1].iter().all(|part| part.ends_with(PART_MARKER)));
+    assert_eq!(parts.concat().replace(PART_MARKER, ""), text);
+
+    let start = Instant::now();
+    let message = |sequence, millis, friend, text: &str| Envelope {
+        sequence,
+        instant: start + Duration::from_millis(millis),
+        time: SystemTime::now(),
+        event: Event::FriendMessage(FriendNumber(friend), MessageType::Normal, text.to_owned()),
+    };
+    let texts = |envelopes: Vec<Envelope>| envelopes.into_iter().map(|e| match e.event {
+        Event::FriendMessage(_, _, text) => (e.sequence, text),
+        event => panic!("unexpected {:?}", event),
+    }).collect::<Vec<_>>();
+
+    let mut joiner = MessageJoiner::new(Duration::from_secs(1));
+    let mut received = vec![];
+    for (i, part) in parts.iter().enumerate() {
+        // Another friend talks in between
+        if i == 1 {
+            received.extend(joiner.push(message(100, 0, 2, "hi")));
+        }
+        received.extend(joiner.push(message(i as u64, i as u64, 1, part)));
+    }
+    assert_eq!(texts(received), vec![(100, "hi".to_owned()), (0, text.clone())]);
+
+    // A part, but nothing follows
+    assert!(joiner.push(message(0, 0, 1, &parts[0])).is_empty());
+    assert!(joiner.flush(start + Duration::from_millis(500)).is_empty());
+    let flushed = joiner.flush(start + Duration::from_secs(2));
+    assert_eq!(texts(flushed), vec![(0, parts[0].replace(PART_MARKER, ""))]);
+
+    // Long messages that weren't split are left alone
+    let long = "a".repeat(100);
+    let mut received = joiner.push(message(0, 0, 1, &long));
+    received.extend(joiner.push(message(1, 1, 1, "short")));
+    assert_eq!(texts(received), vec![(0, long), (1, "short".to_owned())]);
+}
@@ -0,0 +1,238 @@
+use std::sync::mpsc::{SendError, Sender};
+
+use super::{Envelope, Event, FriendNumber, ConferenceNumber};
+use super::Event::*;
+
+/// The variant of an `Event`, without its data
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    ConnectionStatus,
+    FriendRequest,
+    FriendMessage,
+    FriendName,
+    FriendStatusMessage,
+    FriendStatus,
+    FriendConnectionStatus,
+    FriendTyping,
+    FriendReadReceipt,
+    FileControlReceipt,
+    FileChunkRequest,
+    FileReceipt,
+    FileChunkReceipt,
+    ConferenceInvite,
+    ConferenceConnected,
+    ConferenceMessage,
+    ConferenceTitle,
+    ConferencePeerName,
+    ConferencePeerListChanged,
+    LossyPackage,
+    LosslessPackage,
+    Call,
+    CallState,
+    BitRateStatus,
+    AudioReceiveFrame,
+    VideoReceiveFrame,
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match *self {
+            ConnectionStatus(..) => EventKind::ConnectionStatus,
+            FriendRequest(..) => EventKind::FriendRequest,
+            FriendMessage(..) => EventKind::FriendMessage,
+            FriendName(..) => EventKind::FriendName,
+            FriendStatusMessage(..) => EventKind::FriendStatusMessage,
+            FriendStatus(..) => EventKind::FriendStatus,
+            FriendConnectionStatus(..) => EventKind::FriendConnectionStatus,
+            FriendTyping(..) => EventKind::FriendTyping,
+            FriendReadReceipt { .. } => EventKind::FriendReadReceipt,
+            FileControlReceipt { .. } => EventKind::FileControlReceipt,
+            FileChunkRequest { .. } => EventKind::FileChunkRequest,
+            FileReceipt { .. } => EventKind::FileReceipt,
+            FileChunkReceipt { .. } => EventKind::FileChunkReceipt,
+            ConferenceInvite { .. } => EventKind::ConferenceInvite,
+            ConferenceConnected { .. } => EventKind::ConferenceConnected,
+            ConferenceMessage { .. } => EventKind::ConferenceMessage,
+            ConferenceTitle { .. } => EventKind::ConferenceTitle,
+            ConferencePeerName { .. } => EventKind::ConferencePeerName,
+            ConferencePeerListChanged { .. } => EventKind::ConferencePeerListChanged,
+            LossyPackage(..) => EventKind::LossyPackage,
+            LosslessPackage(..) => EventKind::LosslessPackage,
+            Call(..) => EventKind::Call,
+            CallState(..) => EventKind::CallState,
+            BitRateStatus(..) => EventKind::BitRateStatus,
+            AudioReceiveFrame(..) => EventKind::AudioReceiveFrame,
+            VideoReceiveFrame(..) => EventKind::VideoReceiveFrame,
+        }
+    }
+
+    /// The friend the event is about, if any
+    pub fn friend(&self) -> Option<FriendNumber> {
+        match *self {
+            FriendMessage(friend, ..)
+            | FriendName(friend, _)
+            | FriendStatusMessage(friend, _)
+            | FriendStatus(friend, _)
+            | FriendConnectionStatus(friend, _)
+            | FriendTyping(friend, _)
+            | FriendReadReceipt { friend, .. }
+            | FileControlReceipt { friend, .. }
+            | FileChunkRequest { friend, .. }
+            | FileReceipt { friend, .. }
+            | FileChunkReceipt { friend, .. }
+            | ConferenceInvite { friend, .. }
+            | LossyPackage(friend, _)
+            | LosslessPackage(friend, _)
+            | Call(friend, ..)
+            | CallState(friend, _)
+            | BitRateStatus(friend, ..)
+            | AudioReceiveFrame(friend, ..)
+            | VideoReceiveFrame(friend, ..) => Some(friend),
+            _ => None,
+        }
+    }
+
+    /// The conference the event is about, if any
+    pub fn conference(&self) -> Option<ConferenceNumber> {
+        match *self {
+            ConferenceConnected { conference }
+            | ConferenceMessage { conference, .. }
+            | ConferenceTitle { conference, .. }
+            | ConferencePeerName { conference, .. }
+            | ConferencePeerListChanged { conference } => Some(conference),
+            _ => None,
+        }
+    }
+}
+
+/**
+    Selects the events of a subscription, see `Tox::subscribe`.
+
+    A new filter matches every event. Each call narrows it down: an event
+    matches if it is of one of the given kinds, and is about one of the given
+    friends or conferences.
+
+    ```
+    use rstox::core::{EventFilter, EventKind, FriendNumber};
+
+    // Messages and typing notifications of friend 3
+    let filter = EventFilter::new()
+        .kind(EventKind::FriendMessage)
+        .kind(EventKind::FriendTyping)
+        .friend(FriendNumber(3));
+    ```
+*/
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    kinds: Vec<EventKind>,
+    friends: Vec<FriendNumber>,
+    conferences: Vec<ConferenceNumber>,
+}
+
+impl EventFilter {
+    pub fn new() -> EventFilter {
+        EventFilter::default()
+    }
+
+    pub fn kind(mut self, kind: EventKind) -> EventFilter {
+        self.kinds.push(kind);
+        self
+    }
+
+    pub fn friend(mut self, friend: FriendNumber) -> EventFilter {
+        self.friends.push(friend);
+        self
+    }
+
+    pub fn conference(mut self, conference: ConferenceNumber) -> EventFilter {
+        self.conferences.push(conference);
+        self
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        let kind = self.kinds.is_empty() || self.kinds.contains(&event.kind());
+        let subject = (self.friends.is_empty() && self.conferences.is_empty())
+            || event.friend().is_some_and(|f| self.friends.contains(&f))
+            || event.conference().is_some_and(|c| self.conferences.contains(&c));
+        kind && subject
+    }
+}
+
+/// The subscriptions of one `Tox`
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    subscribers: Vec<(EventFilter, Sender<Envelope>)>,
+}
+
+impl Subscribers {
+    pub fn add(&mut self, filter: EventFilter, tx: Sender<Envelope>) {
+        self.subscribers.push((filter, tx));
+    }
+
+    /**
+        Send the envelope to every matching subscriber, cloning it for all
+        but the last one. Returns the envelope if nobody received it.
+
+        Subscribers whose receiver is gone are removed.
+    */
+    pub fn deliver(&mut self, envelope: Envelope) -> Option<Envelope> {
+        let matching = self.subscribers.iter()
+            .enumerate()
+            .filter(|(_, (filter, _))| filter.matches(&envelope.event))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+
+        let mut envelope = Some(envelope);
+        let mut delivered = false;
+        let mut gone = vec![];
+        for (n, &i) in matching.iter().enumerate() {
+            let copy = if n + 1 == matching.len() { envelope.take() } else { envelope.clone() };
+            match self.subscribers[i].1.send(copy.unwrap()) {
+                Ok(()) => delivered = true,
+                Err(SendError(copy)) => {
+                    gone.push(i);
+                    if envelope.is_none() {
+                        envelope = Some(copy);
+                    }
+                },
+            }
+        }
+        for i in gone.into_iter().rev() {
+            self.subscribers.remove(i);
+        }
+
+        if delivered { None } else { envelope }
+    }
+}
+
+#[test]
+fn test_subscribers_fan_out() {
+    use std::sync::mpsc::channel;
+    use std::time::{Instant, SystemTime};
+    use super::MessageType;
+
+    fn envelope(sequence: u64, event: Event) -> Envelope {
+        Envelope { sequence, instant: Instant::now(), time: SystemTime::now(), event }
+    }
+    let message = |friend| FriendMessage(FriendNumber(friend), MessageType::Normal, "hi".into());
+
+    let mut subscribers = Subscribers::default();
+    let (chat_tx, chat) = channel();
+    subscribers.add(EventFilter::new().kind(EventKind::FriendMessage), chat_tx);
+    let (friend_tx, friend) = channel();
+    subscribers.add(EventFilter::new().friend(FriendNumber(1)), friend_tx);
+    let (gone_tx, gone) = channel();
+    subscribers.add(EventFilter::new().friend(FriendNumber(2)), gone_tx);
+    drop(gone);
+
+    assert!(subscribers.deliver(envelope(0, message(1))).is_none());
+    assert!(subscribers.deliver(envelope(1, FriendTyping(FriendNumber(1), true))).is_none());
+    assert!(subscribers.deliver(envelope(2, message(2))).is_none());
+    assert_eq!(subscribers.subscribers.len(), 2);
+    // Nobody wants it, the caller keeps it
+    let typing = subscribers.deliver(envelope(3, FriendTyping(FriendNumber(2), true)));
+    assert_eq!(typing.map(|e| e.sequence), Some(3));
+
+    assert_eq!(chat.try_iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(friend.try_iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![0, 1]);
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::{Tox, FriendNumber};
+use super::errors::SetTypingError;
+
+/**
+    Turns keystrokes into typing notifications.
+
+    Call `key_pressed()` every time the user edits a message for a friend,
+    `stop()` once the message is sent or the input is cleared, and `poll()`
+    periodically, e.g. after each `Tox::tick()`.
+
+    The friend is told that we are typing on the first keystroke only, and that
+    we stopped either on `stop()` or after `idle_timeout` passes without
+    keystrokes, so only one update is sent per transition.
+*/
+pub struct TypingNotifier {
+    idle_timeout: Duration,
+    last_key: HashMap<FriendNumber, Instant>,
+}
+
+impl TypingNotifier {
+    /// Create a notifier that clears the typing status after `idle_timeout`
+    /// without keystrokes
+    pub fn new(idle_timeout: Duration) -> TypingNotifier {
+        TypingNotifier {
+            idle_timeout,
+            last_key: HashMap::new(),
+        }
+    }
+
+    /// Record a keystroke in the conversation with `friend`
+    pub fn key_pressed(&mut self, tox: &mut Tox, friend: FriendNumber) -> Result<(), SetTypingError> {
+        if self.press(friend, Instant::now()) {
+            if let Err(e) = tox.set_typing(friend, true) {
+                self.last_key.remove(&friend);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tell `friend` that we stopped typing, if we have told them otherwise
+    pub fn stop(&mut self, tox: &mut Tox, friend: FriendNumber) -> Result<(), SetTypingError> {
+        if self.last_key.remove(&friend).is_some() {
+            tox.set_typing(friend, false)?;
+        }
+        Ok(())
+    }
+
+    /**
+        Clear the typing status of every friend whose last keystroke is older
+        than the idle timeout.
+
+        Friends that were deleted in the meantime are forgotten silently.
+    */
+    pub fn poll(&mut self, tox: &mut Tox) {
+        for friend in self.expire(Instant::now()) {
+            let _ = tox.set_typing(friend, false);
+        }
+    }
+
+    /// Whether `friend` is currently told that we are typing
+    pub fn is_typing(&self, friend: FriendNumber) -> bool {
+        self.last_key.contains_key(&friend)
+    }
+
+    /// Returns `true` if this keystroke starts typing.
+    fn press(&mut self, friend: FriendNumber, now: Instant) -> bool {
+        self.last_key.insert(friend, now).is_none()
+    }
+
+    /// Returns friends that went idle and forgets them.
+    fn expire(&mut self, now: Instant) -> Vec<FriendNumber> {
+        let timeout = self.idle_timeout;
+        let idle: Vec<FriendNumber> = self.last_key.iter()
+            .filter(|&(_, &last)| now.saturating_duration_since(last) >= timeout)
+            .map(|(&friend, _)| friend)
+            .collect();
+        for friend in &idle {
+            self.last_key.remove(friend);
+        }
+        idle
+    }
+}
+
+#[test]
+fn test_typing_notifier_throttles() {
+    let mut notifier = TypingNotifier::new(Duration::from_secs(5));
+    let start = Instant::now();
+
+    assert!(notifier.press(FriendNumber(0), start));
+    assert!(!notifier.press(FriendNumber(0), start + Duration::from_secs(1)));
+    assert!(notifier.press(FriendNumber(1), start + Duration::from_secs(2)));
+
+    assert!(notifier.expire(start + Duration::from_secs(5)).is_empty());
+    assert_eq!(notifier.expire(start + Duration::from_secs(6)), vec![FriendNumber(0)]);
+    assert!(!notifier.is_typing(FriendNumber(0)));
+    assert!(notifier.is_typing(FriendNumber(1)));
+
+    assert!(notifier.press(FriendNumber(0), start + Duration::from_secs(7)));
+}
@@ -0,0 +1,116 @@
+use std::fmt;
+
+use super::{
+    ll,
+    PUBLIC_KEY_SIZE,
+    SECRET_KEY_SIZE,
+    NOSPAM_SIZE,
+    ADDRESS_SIZE,
+    MAX_NAME_LENGTH,
+    MAX_CUSTOM_PACKET_SIZE,
+    MAX_HOSTNAME_LENGTH,
+    CONFERENCE_ID_SIZE,
+    CONFERENCE_UID_SIZE,
+    FILE_ID_LENGTH,
+};
+
+/// Version of the toxcore library
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The toxcore API version these bindings are written against
+pub const BINDINGS_VERSION: Version = Version { major: 0, minor: 2, patch: 9 };
+
+/// Return the version of the linked toxcore library
+pub fn version() -> Version {
+    unsafe {
+        Version {
+            major: ll::tox_version_major(),
+            minor: ll::tox_version_minor(),
+            patch: ll::tox_version_patch(),
+        }
+    }
+}
+
+/// The reason the linked toxcore can't be used with these bindings
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Incompatibility {
+    /// The library reports that it is not API compatible with
+    /// `BINDINGS_VERSION`
+    Version(Version),
+    /// A size or limit compiled into the bindings differs from the one of the
+    /// library
+    Constant {
+        name: &'static str,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Incompatibility::Version(found) =>
+                write!(fmt, "toxcore {} is not compatible with {}", found, BINDINGS_VERSION),
+            Incompatibility::Constant { name, expected, found } =>
+                write!(fmt, "toxcore has {} = {}, expected {}", name, found, expected),
+        }
+    }
+}
+
+/**
+    Check that the linked toxcore is ABI compatible with these bindings.
+
+    Besides the version, every size the bindings hard-code (keys, addresses,
+    fixed-size buffers) is compared with the value reported by the library,
+    since a mismatch there means reading or writing out of bounds.
+*/
+pub fn check_compatibility() -> Result<(), Incompatibility> {
+    let compatible = unsafe {
+        ll::tox_version_is_compatible(
+            BINDINGS_VERSION.major,
+            BINDINGS_VERSION.minor,
+            BINDINGS_VERSION.patch
+        )
+    };
+    if !compatible {
+        return Err(Incompatibility::Version(version()));
+    }
+
+    let constants: [(&'static str, usize, unsafe extern "C" fn() -> u32); 10] = [
+        ("PUBLIC_KEY_SIZE", PUBLIC_KEY_SIZE, ll::tox_public_key_size),
+        ("SECRET_KEY_SIZE", SECRET_KEY_SIZE, ll::tox_secret_key_size),
+        ("NOSPAM_SIZE", NOSPAM_SIZE, ll::tox_nospam_size),
+        ("ADDRESS_SIZE", ADDRESS_SIZE, ll::tox_address_size),
+        ("MAX_NAME_LENGTH", MAX_NAME_LENGTH, ll::tox_max_name_length),
+        ("MAX_CUSTOM_PACKET_SIZE", MAX_CUSTOM_PACKET_SIZE, ll::tox_max_custom_packet_size),
+        ("MAX_HOSTNAME_LENGTH", MAX_HOSTNAME_LENGTH, ll::tox_max_hostname_length),
+        ("CONFERENCE_ID_SIZE", CONFERENCE_ID_SIZE, ll::tox_conference_id_size),
+        ("CONFERENCE_UID_SIZE", CONFERENCE_UID_SIZE, ll::tox_conference_uid_size),
+        ("FILE_ID_LENGTH", FILE_ID_LENGTH, ll::tox_file_id_length),
+    ];
+    for &(name, expected, query) in constants.iter() {
+        let found = unsafe { query() } as usize;
+        if found != expected {
+            return Err(Incompatibility::Constant { name, expected, found });
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+// The bindings should match the toxcore they are tested against
+fn test_check_compatibility() {
+    assert_eq!(check_compatibility(), Ok(()));
+}
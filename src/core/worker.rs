@@ -0,0 +1,85 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use super::errors::InitError;
+use super::{Tox, ToxOptions, Event};
+
+pub(crate) type Command = Box<dyn FnOnce(&mut Tox) + Send>;
+
+/**
+    A thread that owns a `Tox`, iterates it and runs the commands sent to it
+    between iterations.
+
+    The thread stops after its current iteration once the `Worker` is
+    dropped. Dropping doesn't wait for it, so it doesn't block async
+    runtimes or a `Worker` dropped on the thread itself.
+*/
+pub(crate) struct Worker {
+    commands: mpsc::Sender<Command>,
+}
+
+impl Worker {
+    /// Start a `Tox` created with `Tox::new(opts, data)`, every event goes to
+    /// `sink`
+    pub fn spawn<F>(opts: ToxOptions, data: Option<&[u8]>, sink: F) -> Result<Worker, InitError>
+        where F: FnMut(Event) + Send + 'static
+    {
+        let data = data.map(|data| data.to_vec());
+        let (commands_tx, commands_rx) = mpsc::channel::<Command>();
+        let (init_tx, init_rx) = mpsc::channel();
+
+        let thread = thread::Builder::new()
+            .name("tox".to_owned())
+            .spawn(move || {
+                let tox = match Tox::new(opts, data.as_deref()) {
+                    Ok(tox) => {
+                        init_tx.send(Ok(())).unwrap();
+                        tox
+                    },
+                    Err(err) => {
+                        init_tx.send(Err(err)).unwrap();
+                        return;
+                    }
+                };
+                run(tox, commands_rx, sink);
+            })
+            .expect("failed to spawn the tox thread");
+
+        if let Err(err) = init_rx.recv().unwrap() {
+            thread.join().unwrap();
+            return Err(err);
+        }
+
+        Ok(Worker { commands: commands_tx })
+    }
+
+    /// Queue `command`, fails if the thread has died because of a panic
+    pub fn send(&self, command: Command) -> Result<(), Command> {
+        self.commands.send(command).map_err(|err| err.0)
+    }
+}
+
+fn run<F>(mut tox: Tox, commands: mpsc::Receiver<Command>, mut sink: F)
+    where F: FnMut(Event)
+{
+    loop {
+        // Only the queueing of events runs in the callbacks here, and the
+        // events queued before a panic come with the next iteration
+        if let Ok(events) = tox.iter() {
+            for event in events {
+                sink(event);
+            }
+        }
+
+        let deadline = Instant::now() + tox.iteration_interval();
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match commands.recv_timeout(timeout) {
+                Ok(command) => command(&mut tox),
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+}
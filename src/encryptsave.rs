@@ -1,3 +1,5 @@
+use std::mem::MaybeUninit;
+
 mod ll;
 pub mod errors;
 
@@ -8,36 +10,40 @@ macro_rules! tox_res {
     ( $res:ident <- $rexpr:expr, $err:ident, $fun:expr ) => {
         unsafe {
             let mut $res = $rexpr;
-            let mut $err = ::std::mem::uninitialized();
+            let mut $err = MaybeUninit::uninit();
             if $fun {
                 Ok($res)
             } else {
-                Err($err)
+                Err($err.assume_init())
             }
         }
     };
-    ( $res:ident, $err:ident, $fun:expr ) => {
-        tox_res!(
-            $res <- ::std::mem::uninitialized(),
-            $err,
-            $fun
-        )
-    }
 }
 
 pub const PASS_ENCRYPTION_EXTRA_LENGTH: usize = 80;
+pub const PASS_SALT_LENGTH: usize = ll::PASS_SALT_LENGTH;
 
 /// Determine whether the data has been encrypted.
 pub fn is_encrypted(data: &[u8]) -> bool {
-    unsafe { ll::tox_is_data_encrypted(data.as_ptr()) }
+    data.len() >= ll::ENC_SAVE_MAGIC_LENGTH
+        && unsafe { ll::tox_is_data_encrypted(data.as_ptr()) }
+}
+
+/// Length of the plaintext for `data`, or `InvalidLength` if `data` is too
+/// short to have been produced by the encryption functions.
+fn decrypted_len(data: &[u8]) -> Result<usize, errors::DecryptionError> {
+    data.len().checked_sub(PASS_ENCRYPTION_EXTRA_LENGTH)
+        .ok_or(errors::DecryptionError::InvalidLength)
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct ToxPassKey {
-    passkey: ll::Tox_PassKey
+    passkey: *mut ll::Tox_Pass_Key
 }
 
+// The key is never modified after it has been derived
+unsafe impl Send for ToxPassKey {}
+unsafe impl Sync for ToxPassKey {}
+
 /// ToxPassKey, Symmetric encryption local files.
 ///
 /// # Examples
@@ -58,67 +64,72 @@ pub struct ToxPassKey {
 ///     String::from_utf8_lossy(&plaintext)
 /// );
 /// ```
-#[allow(unused_mut)]
 impl ToxPassKey {
     /// Generate ToxPassKey, using a random salt.
     pub fn new(passphrase: &[u8]) -> Result<ToxPassKey, errors::KeyDerivationError>  {
-        let passkey = tox_res!(
-            passkey,
-            err,
-            ll::tox_derive_key_from_pass(
+        unsafe {
+            let mut err = MaybeUninit::uninit();
+            let passkey = ll::tox_pass_key_derive(
                 passphrase.as_ptr(),
                 passphrase.len(),
-                &mut passkey,
-                &mut err
-            )
-        )?;
-
-        Ok(ToxPassKey { passkey: passkey })
+                err.as_mut_ptr()
+            );
+            ToxPassKey::from_raw(passkey, err)
+        }
     }
 
     /// Generate Tox PassKey, read salt from the data.
     pub fn from(passphrase: &[u8], data: &[u8]) -> Result<ToxPassKey, errors::KeyDerivationError> {
-        ToxPassKey::with(passphrase, unsafe {
-            let mut salt = Vec::with_capacity(ll::PASS_SALT_LENGTH);
-            salt.set_len(ll::PASS_SALT_LENGTH);
-            ll::tox_get_salt(data.as_ptr(), salt.as_mut_ptr());
-            salt
-        })
+        if data.len() < PASS_ENCRYPTION_EXTRA_LENGTH {
+            return Err(errors::KeyDerivationError::InvalidSalt);
+        }
+        let mut salt = vec![0; PASS_SALT_LENGTH];
+        let mut err = MaybeUninit::uninit();
+        if !unsafe { ll::tox_get_salt(data.as_ptr(), salt.as_mut_ptr(), err.as_mut_ptr()) } {
+            return Err(errors::KeyDerivationError::InvalidSalt);
+        }
+        ToxPassKey::with(passphrase, salt)
     }
 
     /// Generate ToxPassKey, using the specified salt.
     pub fn with(passphrase: &[u8], salt: Vec<u8>) -> Result<ToxPassKey, errors::KeyDerivationError> {
-        let passkey = tox_res!(
-            passkey,
-            err,
-            ll::tox_derive_key_with_salt(
+        if salt.len() != PASS_SALT_LENGTH {
+            return Err(errors::KeyDerivationError::InvalidSalt);
+        }
+        unsafe {
+            let mut err = MaybeUninit::uninit();
+            let passkey = ll::tox_pass_key_derive_with_salt(
                 passphrase.as_ptr(),
                 passphrase.len(),
                 salt.as_ptr(),
-                &mut passkey,
-                &mut err
-            )
-        )?;
+                err.as_mut_ptr()
+            );
+            ToxPassKey::from_raw(passkey, err)
+        }
+    }
 
-        Ok(ToxPassKey { passkey: passkey })
+    unsafe fn from_raw(
+        passkey: *mut ll::Tox_Pass_Key,
+        err: MaybeUninit<errors::KeyDerivationError>
+    ) -> Result<ToxPassKey, errors::KeyDerivationError> {
+        if passkey.is_null() {
+            Err(err.assume_init())
+        } else {
+            Ok(ToxPassKey { passkey })
+        }
     }
 
     /// encryption
     pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, errors::EncryptionError> {
         tox_res!(
-            out <- {
-                let len = data.len() + PASS_ENCRYPTION_EXTRA_LENGTH;
-                let mut out = Vec::with_capacity(len);
-                out.set_len(len);
-                out
-            },
+            out <- vec![0; data.len() + PASS_ENCRYPTION_EXTRA_LENGTH],
             err,
             ll::tox_pass_key_encrypt(
+                self.passkey,
                 data.as_ptr(),
                 data.len(),
-                &self.passkey,
                 out.as_mut_ptr(),
-                &mut err
+                err.as_mut_ptr()
             )
         )
     }
@@ -126,33 +137,29 @@ impl ToxPassKey {
     /// decryption
     pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, errors::DecryptionError> {
         tox_res!(
-            out <- {
-                let len = data.len() - PASS_ENCRYPTION_EXTRA_LENGTH;
-                let mut out = Vec::with_capacity(len);
-                out.set_len(len);
-                out
-            },
+            out <- vec![0; decrypted_len(data)?],
             err,
             ll::tox_pass_key_decrypt(
+                self.passkey,
                 data.as_ptr(),
                 data.len(),
-                &self.passkey,
                 out.as_mut_ptr(),
-                &mut err
+                err.as_mut_ptr()
             )
         )
     }
 }
 
+impl Drop for ToxPassKey {
+    fn drop(&mut self) {
+        unsafe { ll::tox_pass_key_free(self.passkey) }
+    }
+}
+
 /// use passphrase encryption
 pub fn pass_encrypt(passphrase: &[u8], data: &[u8]) -> Result<Vec<u8>, errors::EncryptionError> {
     tox_res!(
-        out <- {
-            let len = data.len() + PASS_ENCRYPTION_EXTRA_LENGTH;
-            let mut out = Vec::with_capacity(len);
-            out.set_len(len);
-            out
-        },
+        out <- vec![0; data.len() + PASS_ENCRYPTION_EXTRA_LENGTH],
         err,
         ll::tox_pass_encrypt(
             data.as_ptr(),
@@ -160,7 +167,7 @@ pub fn pass_encrypt(passphrase: &[u8], data: &[u8]) -> Result<Vec<u8>, errors::E
             passphrase.as_ptr(),
             passphrase.len(),
             out.as_mut_ptr(),
-            &mut err
+            err.as_mut_ptr()
         )
     )
 }
@@ -168,12 +175,7 @@ pub fn pass_encrypt(passphrase: &[u8], data: &[u8]) -> Result<Vec<u8>, errors::E
 /// use passphrase decryption
 pub fn pass_decrypt(passphrase: &[u8], data: &[u8]) -> Result<Vec<u8>, errors::DecryptionError> {
     tox_res!(
-        out <- {
-            let len = data.len() - PASS_ENCRYPTION_EXTRA_LENGTH;
-            let mut out = Vec::with_capacity(len);
-            out.set_len(len);
-            out
-        },
+        out <- vec![0; decrypted_len(data)?],
         err,
         ll::tox_pass_decrypt(
             data.as_ptr(),
@@ -181,7 +183,34 @@ pub fn pass_decrypt(passphrase: &[u8], data: &[u8]) -> Result<Vec<u8>, errors::D
             passphrase.as_ptr(),
             passphrase.len(),
             out.as_mut_ptr(),
-            &mut err
+            err.as_mut_ptr()
         )
     )
 }
+
+#[test]
+// Data shorter than the encryption overhead can't be decrypted
+fn test_decrypt_short_data() {
+    assert_eq!(pass_decrypt(b"rstox", b""), Err(errors::DecryptionError::InvalidLength));
+    assert_eq!(pass_decrypt(b"rstox", &[0; PASS_ENCRYPTION_EXTRA_LENGTH - 1]),
+        Err(errors::DecryptionError::InvalidLength));
+    assert!(!is_encrypted(b"toxEsa"));
+}
+
+#[test]
+fn test_decrypt_garbage() {
+    let garbage = [0x42; 2 * PASS_ENCRYPTION_EXTRA_LENGTH];
+    assert!(!is_encrypted(&garbage));
+    assert_eq!(pass_decrypt(b"rstox", &garbage), Err(errors::DecryptionError::BadFormat));
+    assert_eq!(ToxPassKey::from(b"rstox", &garbage).err(),
+        Some(errors::KeyDerivationError::InvalidSalt));
+}
+
+#[test]
+fn test_pass_round_trip() {
+    let data = b"rstox is a Rust wrapper for toxcore.";
+    let ciphertext = pass_encrypt(b"rstox", data).unwrap();
+    assert!(is_encrypted(&ciphertext));
+    assert_eq!(pass_decrypt(b"rstox", &ciphertext).unwrap(), &data[..]);
+    assert_eq!(pass_decrypt(b"xotsr", &ciphertext), Err(errors::DecryptionError::Failed));
+}
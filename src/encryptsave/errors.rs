@@ -3,7 +3,10 @@
 pub enum KeyDerivationError {
     #[doc(hidden)] NoError = 0,
     NullError = 1,
-    Failed
+    Failed,
+    /// The salt is not `PASS_SALT_LENGTH` bytes long, or the data to read it
+    /// from is not encrypted. This error is never reported by toxcore itself.
+    InvalidSalt = 100,
 }
 
 #[repr(C)]
@@ -25,3 +28,11 @@ pub enum DecryptionError {
     KeyDerivationFailed,
     Failed
 }
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum GetSaltError {
+    #[doc(hidden)] NoError = 0,
+    NullError = 1,
+    BadFormat,
+}
@@ -3,34 +3,36 @@ use crate::encryptsave::errors;
 
 pub const PASS_SALT_LENGTH: usize = 32;
 pub const PASS_KEY_LENGTH: usize = 32;
+pub const ENC_SAVE_MAGIC_LENGTH: usize = 8;
 
-#[repr(C)]
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
-pub struct Tox_PassKey {
-    salt: [u8; PASS_SALT_LENGTH],
-    key: [u8; PASS_KEY_LENGTH]
-}
+/// Opaque key derived from a passphrase, allocated by toxcore
+#[allow(non_camel_case_types)]
+pub enum Tox_Pass_Key {}
 
-#[link(name = "toxencryptsave")]
+// Since toxcore 0.2 `toxencryptsave` is built into `libtoxcore`
+#[link(name = "toxcore")]
 extern "C" {
-    pub fn tox_get_salt(data: *const u8, salt: *mut u8) -> bool;
-    pub fn tox_derive_key_from_pass(
+    pub fn tox_get_salt(
+        data: *const u8,
+        salt: *mut u8,
+        error: *mut errors::GetSaltError
+    ) -> bool;
+    pub fn tox_pass_key_free(key: *mut Tox_Pass_Key);
+    pub fn tox_pass_key_derive(
         passphrase: *const u8,
         pplength: usize,
-        out_key: *mut Tox_PassKey,
         error: *mut errors::KeyDerivationError
-    ) -> bool;
-    pub fn tox_derive_key_with_salt(
+    ) -> *mut Tox_Pass_Key;
+    pub fn tox_pass_key_derive_with_salt(
         passphrase: *const u8,
         pplength: usize,
         salt: *const u8,
-        out_key: *mut Tox_PassKey,
         error: *mut errors::KeyDerivationError
-    ) -> bool;
+    ) -> *mut Tox_Pass_Key;
     pub fn tox_pass_key_encrypt(
+        key: *const Tox_Pass_Key,
         data: *const u8,
         data_len: usize,
-        key: *const Tox_PassKey,
         out: *mut u8,
         error: *mut errors::EncryptionError
     ) -> bool;
@@ -43,9 +45,9 @@ extern "C" {
         error: *mut errors::EncryptionError
     ) -> bool;
     pub fn tox_pass_key_decrypt(
+        key: *const Tox_Pass_Key,
         data: *const u8,
         length: usize,
-        key: *const Tox_PassKey,
         out: *mut u8,
         error: *mut errors::DecryptionError
     ) -> bool;
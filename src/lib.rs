@@ -4,5 +4,7 @@
 extern crate libc;
 
 pub mod core;
-// pub mod av;
-// pub mod encryptsave;
+#[cfg(feature = "av")]
+pub mod av;
+#[cfg(feature = "encryptsave")]
+pub mod encryptsave;